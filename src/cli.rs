@@ -0,0 +1,201 @@
+use clap::{Parser, Subcommand};
+use rust_chip8_emulator::audio::{Tone, Waveform};
+use rust_chip8_emulator::disasm::Syntax;
+use rust_chip8_emulator::image::ImageFormat;
+use rust_chip8_emulator::{FontSet, Options, Platform, FRAME_RATE};
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+pub struct Args {
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+
+    #[clap(short, required = true)]
+    pub rom_file_path: Option<String>,
+
+    // Quirk set of the interpreter to emulate, the quirks below override it
+    #[clap(long, default_value = "schip")]
+    pub platform: Platform,
+    #[clap(long)]
+    pub shift_quirk: Option<bool>,
+    #[clap(long)]
+    pub jump_quirk: Option<bool>,
+    #[clap(long)]
+    pub load_store_quirk: Option<bool>,
+    #[clap(long)]
+    pub key_release_quirk: Option<bool>,
+    #[clap(long)]
+    pub i_overflow_quirk: Option<bool>,
+    #[clap(long)]
+    pub vf_reset_quirk: Option<bool>,
+    #[clap(long)]
+    pub clip_quirk: Option<bool>,
+    #[clap(long)]
+    pub display_wait_quirk: Option<bool>,
+    #[clap(long, value_parser = clap::value_parser!(u8).range(1..=16))]
+    pub stack_depth: Option<u8>,
+    // Keep the stack in memory, growing down from this address
+    #[clap(long, value_parser = parse_address)]
+    pub memory_stack: Option<u16>,
+    #[clap(long, conflicts_with = "memory-stack")]
+    pub register_stack: bool,
+    #[clap(long)]
+    pub font: Option<FontSet>,
+    #[clap(long, value_parser = parse_font_address)]
+    pub font_address: Option<u16>,
+
+    #[clap(long, default_value_t = 440.0)]
+    pub frequency: f32,
+    #[clap(long, default_value = "square")]
+    pub waveform: Waveform,
+    #[clap(long, default_value_t = 0.25)]
+    pub volume: f32,
+    #[clap(long)]
+    pub mute: bool,
+
+    // Seed of the CXNN random numbers, random unless given
+    #[clap(long)]
+    pub seed: Option<u64>,
+
+    // Record the session to a movie file, or replay one instead of playing.
+    // Only sessions in a window are recorded
+    #[clap(long, conflicts_with = "headless")]
+    pub record: Option<PathBuf>,
+    #[clap(long, conflicts_with = "record")]
+    pub replay: Option<PathBuf>,
+
+    // Run without opening a window, for at most `frames` frames or until the
+    // ROM halts, then write the requested dumps
+    #[clap(long)]
+    pub headless: bool,
+    #[clap(long, default_value_t = 600)]
+    pub frames: u64,
+    #[clap(long)]
+    pub screenshot: Option<PathBuf>,
+    #[clap(long)]
+    pub screenshot_format: Option<ImageFormat>,
+    #[clap(long)]
+    pub dump: Option<PathBuf>,
+    #[clap(long)]
+    pub wav: Option<PathBuf>,
+
+    // Keep the SUPER-CHIP RPL user flags in memory only
+    #[clap(long)]
+    pub no_persist_flags: bool,
+
+    // How far back holding Backspace rewinds, 0 disables rewinding
+    #[clap(long, default_value_t = 10)]
+    pub rewind_seconds: u32,
+    #[clap(long, default_value_t = 64)]
+    pub rewind_megabytes: usize,
+
+    #[clap(long)]
+    pub cycles_per_frame: Option<u32>,
+    #[clap(long, conflicts_with = "cycles-per-frame")]
+    pub ips: Option<u32>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    // Run every ROM of a manifest headless and compare its final screen with
+    // a golden image
+    Regress {
+        manifest: PathBuf,
+        // Write the golden images instead of comparing with them
+        #[clap(long)]
+        update: bool,
+    },
+    // Print the address, raw bytes and mnemonic of every instruction of a
+    // ROM, telling code from data by following its control flow
+    Disasm {
+        rom: PathBuf,
+        #[clap(long, default_value = "cowgod")]
+        syntax: Syntax,
+        // Platform whose instruction set the ROM is written for
+        #[clap(long, default_value = "xochip")]
+        platform: Platform,
+    },
+}
+
+impl Args {
+    pub fn options(&self) -> Options {
+        let mut options = self.platform.options();
+        if let Some(quirk) = self.shift_quirk {
+            options.put_value_of_vy_into_vx_before_shifting = quirk;
+        }
+        if let Some(quirk) = self.jump_quirk {
+            options.jump_to_nnn_plus_the_value_in_v0 = quirk;
+        }
+        if let Some(quirk) = self.load_store_quirk {
+            options.increment_i_when_storing_loading_memory = quirk;
+        }
+        if let Some(quirk) = self.key_release_quirk {
+            options.wait_for_key_release = quirk;
+        }
+        if let Some(quirk) = self.i_overflow_quirk {
+            options.set_vf_on_i_overflow = quirk;
+        }
+        if let Some(quirk) = self.vf_reset_quirk {
+            options.reset_vf_on_logic_ops = quirk;
+        }
+        if let Some(quirk) = self.clip_quirk {
+            options.clip_sprites = quirk;
+        }
+        if let Some(quirk) = self.display_wait_quirk {
+            options.wait_for_vblank = quirk;
+        }
+        if let Some(depth) = self.stack_depth {
+            options.stack_depth = depth;
+        }
+        if let Some(address) = self.memory_stack {
+            options.stack_address = Some(address);
+        }
+        if self.register_stack {
+            options.stack_address = None;
+        }
+        if let Some(font) = self.font {
+            options.font = font;
+        }
+        if let Some(address) = self.font_address {
+            options.font_address = address;
+        }
+        options
+    }
+
+    // Instructions per frame, if given either directly or per second
+    pub fn cycles(&self) -> Option<u32> {
+        self.cycles_per_frame
+            .or_else(|| self.ips.map(|ips| ips / FRAME_RATE))
+    }
+
+    pub fn tone(&self) -> Tone {
+        Tone {
+            frequency: self.frequency,
+            waveform: self.waveform,
+            volume: self.volume.clamp(0.0, 1.0),
+            muted: self.mute,
+        }
+    }
+}
+
+// Parses a 12-bit address, written in hexadecimal with or without 0x
+fn parse_address(s: &str) -> Result<u16, String> {
+    let digits = s.trim_start_matches("0x").trim_start_matches("0X");
+    match u16::from_str_radix(digits, 16) {
+        Ok(address) if address <= 0xFFF => Ok(address),
+        _ => Err(format!("Invalid address: {}", s)),
+    }
+}
+
+// Parses the font address, the fonts have to fit below the ROM
+fn parse_font_address(s: &str) -> Result<u16, String> {
+    let address = parse_address(s)?;
+    let options = Options {
+        font_address: address,
+        ..Options::default()
+    };
+    options.validate().map_err(|error| error.to_string())?;
+    Ok(address)
+}
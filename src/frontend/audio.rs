@@ -0,0 +1,60 @@
+use rust_chip8_emulator::audio::{Audio, Oscillator, Tone};
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::Sdl;
+
+struct ToneCallback {
+    oscillator: Oscillator,
+}
+
+impl AudioCallback for ToneCallback {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        for sample in out.iter_mut() {
+            *sample = self.oscillator.next_sample();
+        }
+    }
+}
+
+// SDL2 audio device that plays the tone while the buzzer sounds
+pub struct Speaker {
+    device: AudioDevice<ToneCallback>,
+    playing: bool,
+}
+
+impl Speaker {
+    // Opens the default playback device, which a machine may not have
+    pub fn new(sdl_context: &Sdl, tone: Tone) -> Result<Speaker, String> {
+        let audio_subsystem = sdl_context.audio()?;
+        let desired_spec = AudioSpecDesired {
+            freq: Some(44100),
+            channels: Some(1),
+            samples: None,
+        };
+        let device = audio_subsystem.open_playback(None, &desired_spec, |spec| ToneCallback {
+            oscillator: Oscillator::new(tone, spec.freq as u32),
+        })?;
+
+        Ok(Speaker {
+            device,
+            playing: false,
+        })
+    }
+}
+
+impl Audio for Speaker {
+    fn update(&mut self, playing: bool) {
+        if playing != self.playing {
+            if playing {
+                self.device.resume();
+            } else {
+                self.device.pause();
+            }
+            self.playing = playing;
+        }
+    }
+
+    fn set_pattern(&mut self, pattern: &[u8; 16], pitch: u8) {
+        self.device.lock().oscillator.set_pattern(pattern, pitch);
+    }
+}
@@ -0,0 +1,4 @@
+pub mod audio;
+pub mod input;
+pub mod scheduler;
+pub mod video;
@@ -0,0 +1,43 @@
+use rust_chip8_emulator::FRAME_RATE;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Most frames emulated back to back to catch up, further behind than this
+// the missed frames are dropped
+pub const MAX_CATCH_UP_FRAMES: u32 = 4;
+
+// Paces emulated frames against the wall clock
+pub struct Scheduler {
+    frame_duration: Duration,
+    next_frame: Instant,
+}
+
+impl Scheduler {
+    pub fn new() -> Scheduler {
+        Scheduler {
+            frame_duration: Duration::from_secs(1) / FRAME_RATE,
+            next_frame: Instant::now(),
+        }
+    }
+
+    // Sleeps until the next frame is due and returns how many frames to run
+    pub fn wait(&mut self) -> u32 {
+        let now = Instant::now();
+        if now < self.next_frame {
+            thread::sleep(self.next_frame - now);
+        }
+
+        // Catch up on frames missed while the host was busy, but when too far
+        // behind drop them all and carry on from now instead of running a burst
+        let now = Instant::now();
+        let behind = now.saturating_duration_since(self.next_frame);
+        let due = 1 + (behind.as_nanos() / self.frame_duration.as_nanos()) as u32;
+        if due > MAX_CATCH_UP_FRAMES {
+            self.next_frame = now + self.frame_duration;
+            1
+        } else {
+            self.next_frame += self.frame_duration * due;
+            due
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use rust_chip8_emulator::graphics::{Framebuffer, Video, LORES_HEIGHT, LORES_WIDTH};
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+use sdl2::Sdl;
+
+pub const SCALE: u32 = 10;
+
+// Colors of the background, the first plane, the second plane and both
+pub const PALETTE: [Color; 4] = [
+    Color::RGB(0, 0, 0),
+    Color::RGB(255, 255, 255),
+    Color::RGB(170, 170, 170),
+    Color::RGB(85, 85, 85),
+];
+
+// SDL2 window backed display
+pub struct Display {
+    canvas: Canvas<Window>,
+    palette: [Color; 4],
+}
+
+impl Display {
+    pub fn new(sdl_context: &Sdl) -> Display {
+        // Create an SDL2 window with canvas
+        let video_subsystem = sdl_context.video().unwrap();
+        let window = video_subsystem
+            .window(
+                "Rust CHIP-8 interpreter",
+                LORES_WIDTH as u32 * SCALE,
+                LORES_HEIGHT as u32 * SCALE,
+            )
+            .position_centered()
+            .build()
+            .unwrap();
+        let canvas = window.into_canvas().build().unwrap();
+
+        Display {
+            canvas,
+            palette: PALETTE,
+        }
+    }
+}
+
+impl Video for Display {
+    fn clear(&mut self) {
+        self.canvas.set_draw_color(self.palette[0]);
+        self.canvas.clear();
+        self.canvas.present();
+    }
+
+    fn draw(&mut self, framebuffer: &Framebuffer) {
+        // Clear the canvas
+        self.canvas.set_draw_color(self.palette[0]);
+        self.canvas.clear();
+
+        // Pixels are half as large in high resolution
+        let scale = SCALE * LORES_WIDTH as u32 / framebuffer.width() as u32;
+
+        // Draw pixels to the display
+        for (y, row) in framebuffer.rows().enumerate() {
+            for (x, &color) in row.iter().enumerate() {
+                if color != 0 {
+                    self.canvas.set_draw_color(self.palette[color as usize]);
+                    let x = ((x as u32) * scale) as i32;
+                    let y = ((y as u32) * scale) as i32;
+                    self.canvas
+                        .fill_rect(Rect::new(x, y, scale, scale))
+                        .expect("Failed to draw pixel");
+                }
+            }
+        }
+        self.canvas.present();
+    }
+}
@@ -0,0 +1,174 @@
+use crate::cli::Args;
+use rust_chip8_emulator::audio::WavAudio;
+use rust_chip8_emulator::image::ImageFormat;
+use rust_chip8_emulator::movie::Movie;
+use rust_chip8_emulator::{Chip8, Options};
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+// Exit codes of a headless run
+pub const EXIT_SUCCESS: i32 = 0;
+pub const EXIT_MACHINE_ERROR: i32 = 1; // The machine stopped on an error
+pub const EXIT_OUTPUT_ERROR: i32 = 3; // A dump could not be written
+pub const EXIT_DESYNC: i32 = 4; // A replayed movie went out of sync
+
+// Runs the ROM without a window until it halts or `args.frames` frames have
+// passed, returns the exit code of the process
+pub fn run(args: &Args, rom: &[u8]) -> i32 {
+    let mut chip8 = match machine(args, args.options(), rom) {
+        Ok(chip8) => chip8,
+        Err(code) => return code,
+    };
+
+    // Headless runs are reproducible, the seed is 0 unless given
+    chip8.set_seed(args.seed.unwrap_or(0));
+    if let Some(cycles) = args.cycles() {
+        chip8.set_cycles_per_frame(cycles);
+    }
+
+    // Stop at 00FD or at a jump to itself, the way test ROMs end
+    let mut code = EXIT_SUCCESS;
+    while chip8.frame_count() < args.frames && !chip8.is_halted() && !chip8.is_spinning() {
+        if let Err(error) = chip8.run_frame() {
+            eprintln!("{} in frame {}", error, chip8.frame_count());
+            code = EXIT_MACHINE_ERROR;
+            break;
+        }
+    }
+
+    // The dumps show the state at the error as well
+    match write_dumps(args, &chip8) {
+        Ok(()) => code,
+        Err(error) => {
+            eprintln!("{}", error);
+            EXIT_OUTPUT_ERROR
+        }
+    }
+}
+
+// Replays a movie without a window and reports whether it stayed in sync,
+// returns the exit code of the process
+pub fn replay(args: &Args, rom: &[u8], movie: &Movie) -> i32 {
+    let mut chip8 = match machine(args, movie.options, rom) {
+        Ok(chip8) => chip8,
+        Err(code) => return code,
+    };
+    if let Err(error) = movie.start(&mut chip8) {
+        eprintln!("{}", error);
+        return EXIT_MACHINE_ERROR;
+    }
+
+    let mut code = EXIT_SUCCESS;
+    for frame in 0..movie.frames.len() {
+        match movie.replay_frame(frame, &mut chip8) {
+            Ok(true) => {}
+            Ok(false) => {
+                eprintln!(
+                    "Desync at frame {}: expected state {:016x}, got {:016x}",
+                    frame,
+                    movie.frames[frame].state_hash,
+                    chip8.state_hash()
+                );
+                code = EXIT_DESYNC;
+                break;
+            }
+            Err(error) => {
+                eprintln!("{} in frame {}", error, frame);
+                code = EXIT_MACHINE_ERROR;
+                break;
+            }
+        }
+    }
+    if code == EXIT_SUCCESS {
+        println!("Replayed {} frames in sync", movie.frames.len());
+    }
+
+    match write_dumps(args, &chip8) {
+        Ok(()) => code,
+        Err(error) => {
+            eprintln!("{}", error);
+            EXIT_OUTPUT_ERROR
+        }
+    }
+}
+
+fn machine(args: &Args, options: Options, rom: &[u8]) -> Result<Chip8, i32> {
+    let mut chip8 = Chip8::headless(options)
+        .and_then(|mut chip8| chip8.load_rom(rom).map(|()| chip8))
+        .map_err(|error| {
+            eprintln!("{}", error);
+            EXIT_MACHINE_ERROR
+        })?;
+
+    // The buzzer is rendered to a WAV file, which is completed when the
+    // machine is dropped
+    if let Some(path) = &args.wav {
+        match WavAudio::create(path, args.tone()) {
+            Ok(wav) => chip8.set_audio(Box::new(wav)),
+            Err(error) => {
+                eprintln!("Failed to write {}: {}", path.display(), error);
+                return Err(EXIT_OUTPUT_ERROR);
+            }
+        }
+    }
+    Ok(chip8)
+}
+
+// Writes the screenshot and the memory/register dump that were asked for
+fn write_dumps(args: &Args, chip8: &Chip8) -> Result<(), String> {
+    if let Some(path) = &args.screenshot {
+        let format = args
+            .screenshot_format
+            .or_else(|| ImageFormat::from_path(path))
+            .unwrap_or(ImageFormat::Png);
+        let image = format
+            .encode(chip8.framebuffer())
+            .map_err(|error| format!("Failed to encode screenshot: {}", error))?;
+        write(path, &image)?;
+    }
+    if let Some(path) = &args.dump {
+        write(path, dump(chip8).as_bytes())?;
+    }
+    Ok(())
+}
+
+fn write(path: &Path, bytes: &[u8]) -> Result<(), String> {
+    fs::write(path, bytes).map_err(|error| format!("Failed to write {}: {}", path.display(), error))
+}
+
+// Registers, timers, stack and a hex dump of memory, as text
+pub fn dump(chip8: &Chip8) -> String {
+    let mut text = String::new();
+    let _ = writeln!(text, "frame {}", chip8.frame_count());
+    let _ = writeln!(
+        text,
+        "pc {:04X}  i {:04X}  sp {}  dt {:02X}  st {:02X}",
+        chip8.pc(),
+        chip8.i(),
+        chip8.sp(),
+        chip8.delay_timer(),
+        chip8.sound_timer()
+    );
+    let registers: Vec<String> = (0..16)
+        .map(|index| format!("v{:X} {:02X}", index, chip8.v(index)))
+        .collect();
+    let _ = writeln!(text, "{}", registers.join("  "));
+    let _ = match chip8.stack() {
+        Ok(stack) => {
+            let stack: Vec<String> = stack
+                .iter()
+                .map(|address| format!("{:04X}", address))
+                .collect();
+            writeln!(text, "stack [{}]", stack.join(" "))
+        }
+        Err(error) => writeln!(text, "stack unreadable: {}", error),
+    };
+    let _ = writeln!(text);
+
+    for (line, bytes) in chip8.memory().chunks(16).enumerate() {
+        let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        let _ = writeln!(text, "{:04X}  {}", line * 16, hex.join(" "));
+    }
+    text
+}
@@ -0,0 +1,198 @@
+use crate::cpu::FRAME_RATE;
+use std::f32::consts::PI;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+// An audio sink that is told once per frame whether the buzzer sounds
+pub trait Audio {
+    fn update(&mut self, playing: bool);
+
+    // XO-CHIP replaces the tone with a 128-bit pattern played at a pitch
+    fn set_pattern(&mut self, _pattern: &[u8; 16], _pitch: u8) {}
+}
+
+// Bits per second an XO-CHIP audio pattern is played at for `pitch`
+pub fn pattern_rate(pitch: u8) -> f32 {
+    4000.0 * 2f32.powf((pitch as f32 - 64.0) / 48.0)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle,
+}
+
+impl FromStr for Waveform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Waveform, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "square" => Ok(Waveform::Square),
+            "sine" => Ok(Waveform::Sine),
+            "triangle" => Ok(Waveform::Triangle),
+            _ => Err(format!("Unknown waveform: {}", s)),
+        }
+    }
+}
+
+// The tone played while the sound timer is active
+#[derive(Clone, Copy, Debug)]
+pub struct Tone {
+    pub frequency: f32, // Frequency in Hz
+    pub waveform: Waveform,
+    pub volume: f32, // 0.0 through 1.0
+    pub muted: bool,
+}
+
+impl Default for Tone {
+    fn default() -> Tone {
+        Tone {
+            frequency: 440.0,
+            waveform: Waveform::Square,
+            volume: 0.25,
+            muted: false,
+        }
+    }
+}
+
+// Generates the samples of a tone, shared by the audio backends
+pub struct Oscillator {
+    tone: Tone,
+    sample_rate: f32,
+    phase: f32,
+    pattern: Option<([u8; 16], f32)>, // XO-CHIP pattern and its bit rate
+}
+
+impl Oscillator {
+    pub fn new(tone: Tone, sample_rate: u32) -> Oscillator {
+        Oscillator {
+            tone,
+            sample_rate: sample_rate as f32,
+            phase: 0.0,
+            pattern: None,
+        }
+    }
+
+    pub fn set_pattern(&mut self, pattern: &[u8; 16], pitch: u8) {
+        self.pattern = Some((*pattern, pattern_rate(pitch)));
+    }
+
+    pub fn next_sample(&mut self) -> f32 {
+        let sample = if let Some((pattern, rate)) = self.pattern {
+            // The phase walks through the 128 bits of the pattern
+            let bit = (self.phase * 128.0) as usize;
+            self.phase = (self.phase + rate / 128.0 / self.sample_rate) % 1.0;
+            if pattern[bit / 8] & (0x80 >> (bit % 8)) != 0 {
+                1.0
+            } else {
+                -1.0
+            }
+        } else {
+            self.next_tone_sample()
+        };
+
+        if self.tone.muted {
+            0.0
+        } else {
+            sample * self.tone.volume
+        }
+    }
+
+    fn next_tone_sample(&mut self) -> f32 {
+        let sample = match self.tone.waveform {
+            Waveform::Square => {
+                if self.phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Sine => (2.0 * PI * self.phase).sin(),
+            Waveform::Triangle => 4.0 * (self.phase - 0.5).abs() - 1.0,
+        };
+        self.phase = (self.phase + self.tone.frequency / self.sample_rate) % 1.0;
+        sample
+    }
+}
+
+// Audio sink that discards everything, for headless use
+pub struct NullAudio;
+
+impl Audio for NullAudio {
+    fn update(&mut self, _playing: bool) {}
+}
+
+// Audio sink that renders the buzzer into a 16-bit mono WAV file
+pub struct WavAudio {
+    writer: BufWriter<File>,
+    oscillator: Oscillator,
+    samples_per_frame: u32,
+    samples_written: u32,
+}
+
+impl WavAudio {
+    pub const SAMPLE_RATE: u32 = 44100;
+
+    pub fn create(path: &Path, tone: Tone) -> io::Result<WavAudio> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        // RIFF header, the sizes are filled in when the file is closed
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(b"WAVEfmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?; // Format chunk size
+        writer.write_all(&1u16.to_le_bytes())?; // PCM
+        writer.write_all(&1u16.to_le_bytes())?; // Mono
+        writer.write_all(&WavAudio::SAMPLE_RATE.to_le_bytes())?;
+        writer.write_all(&(WavAudio::SAMPLE_RATE * 2).to_le_bytes())?; // Byte rate
+        writer.write_all(&2u16.to_le_bytes())?; // Block align
+        writer.write_all(&16u16.to_le_bytes())?; // Bits per sample
+        writer.write_all(b"data")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+
+        Ok(WavAudio {
+            writer,
+            oscillator: Oscillator::new(tone, WavAudio::SAMPLE_RATE),
+            samples_per_frame: WavAudio::SAMPLE_RATE / FRAME_RATE,
+            samples_written: 0,
+        })
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let data_size = self.samples_written * 2;
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer.write_all(&(36 + data_size).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(40))?;
+        self.writer.write_all(&data_size.to_le_bytes())?;
+        self.writer.flush()
+    }
+}
+
+impl Audio for WavAudio {
+    fn update(&mut self, playing: bool) {
+        for _ in 0..self.samples_per_frame {
+            let sample = if playing {
+                self.oscillator.next_sample()
+            } else {
+                0.0
+            };
+            let sample = (sample * i16::MAX as f32) as i16;
+            if self.writer.write_all(&sample.to_le_bytes()).is_ok() {
+                self.samples_written += 1;
+            }
+        }
+    }
+
+    fn set_pattern(&mut self, pattern: &[u8; 16], pitch: u8) {
+        self.oscillator.set_pattern(pattern, pitch);
+    }
+}
+
+impl Drop for WavAudio {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
@@ -0,0 +1,350 @@
+use crate::audio::Audio;
+use crate::cpu::{Options, CPU};
+use crate::error::{Chip8Error, StateError};
+use crate::graphics::{Framebuffer, NullDisplay, Video};
+use crate::instruction::{decode, Instruction};
+use crate::rng::Rng;
+use crate::state;
+use crate::storage::{fnv1a, FlagStorage};
+
+/// A complete CHIP-8 machine.
+///
+/// This is the embedding API of the interpreter: load a ROM, drive it by
+/// cycles or by frames, feed it key presses and inspect its state.
+pub struct Chip8 {
+    cpu: CPU,
+}
+
+impl Chip8 {
+    /// Creates a machine that presents its framebuffer to `display`.
+    ///
+    /// Fails when the options describe a machine that cannot exist, such as
+    /// fonts that would overlap the ROM.
+    pub fn new(display: Box<dyn Video>, options: Options) -> Result<Chip8, Chip8Error> {
+        options.validate()?;
+        Ok(Chip8 {
+            cpu: CPU::new(display, options),
+        })
+    }
+
+    /// Creates a machine without any video output.
+    pub fn headless(options: Options) -> Result<Chip8, Chip8Error> {
+        Chip8::new(Box::new(NullDisplay::new()), options)
+    }
+
+    /// Plays the buzzer on `audio` instead of discarding it.
+    pub fn set_audio(&mut self, audio: Box<dyn Audio>) {
+        self.cpu.audio = audio;
+    }
+
+    /// Keeps the RPL user flags in `flags`, which are loaded right away and
+    /// saved whenever FX75 executes.
+    pub fn set_flag_storage(&mut self, mut flags: Box<dyn FlagStorage>) {
+        if let Some(rpl) = flags.load() {
+            self.cpu.rpl = rpl;
+        }
+        self.cpu.flags = flags;
+    }
+
+    /// Loads a ROM into memory at 0x200.
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), Chip8Error> {
+        self.cpu.load_rom(rom)
+    }
+
+    /// Returns the hash of the loaded ROM, which identifies it in save states.
+    pub fn rom_hash(&self) -> u64 {
+        self.cpu.rom_hash
+    }
+
+    /// Serializes the whole machine into a save state.
+    ///
+    /// The state includes the options, so it restores the machine exactly as
+    /// it was, but not the video and audio sinks.
+    pub fn save_state(&self) -> Vec<u8> {
+        state::save(&self.cpu)
+    }
+
+    /// Like `save_state`, but writes into `state` to reuse its allocation.
+    pub fn save_state_into(&self, state: &mut Vec<u8>) {
+        state::save_into(&self.cpu, state)
+    }
+
+    /// Returns a hash of the whole machine state, equal for equal states.
+    pub fn state_hash(&self) -> u64 {
+        fnv1a(&self.save_state())
+    }
+
+    /// Restores a save state made by `save_state`.
+    ///
+    /// States made for another ROM or by an incompatible version are refused
+    /// and leave the machine untouched.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        state::load(&mut self.cpu, state)
+    }
+
+    /// Seeds the random number generator of CXNN.
+    ///
+    /// Machines start out with seed 0, so runs are reproducible unless a
+    /// frontend picks a seed of its own.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.cpu.rng = Rng::new(seed);
+    }
+
+    /// Executes a single instruction.
+    ///
+    /// Emulated time advances with every instruction: the timers tick once
+    /// each `cycles_per_frame` instructions, independent of the wall clock.
+    pub fn step(&mut self) -> Result<(), Chip8Error> {
+        self.cpu.step()
+    }
+
+    /// Executes `cycles` instructions, stopping at the first error.
+    pub fn run_cycles(&mut self, cycles: u32) -> Result<(), Chip8Error> {
+        for _ in 0..cycles {
+            self.cpu.step()?;
+        }
+        Ok(())
+    }
+
+    /// Executes the remaining instructions of the current 60 Hz frame.
+    pub fn run_frame(&mut self) -> Result<(), Chip8Error> {
+        self.cpu.run_frame()
+    }
+
+    /// Executes `opcode` as if it had been fetched from the program counter.
+    ///
+    /// Like `step`, this moves the program counter past the opcode first, so
+    /// calls return to the next instruction and a blocking FX0A stays put.
+    /// Emulated time does not advance.
+    pub fn execute_instruction(&mut self, opcode: u16) -> Result<(), Chip8Error> {
+        self.cpu.pc = self.cpu.pc.wrapping_add(2);
+        self.cpu.execute_instruction(opcode)
+    }
+
+    /// Decrements the delay and sound timers once, as a 60 Hz tick would.
+    ///
+    /// `step` and `run_frame` already do this at every frame boundary, this is
+    /// for frontends that need an extra tick outside of the emulated frame.
+    pub fn tick_timers(&mut self) {
+        self.cpu.tick_timers();
+    }
+
+    /// Returns the number of instructions executed per 60 Hz frame.
+    pub fn cycles_per_frame(&self) -> u32 {
+        self.cpu.cycles_per_frame
+    }
+
+    /// Sets the number of instructions executed per 60 Hz frame.
+    pub fn set_cycles_per_frame(&mut self, cycles: u32) {
+        self.cpu.cycles_per_frame = cycles.max(1);
+    }
+
+    /// Returns the number of frames emulated since the machine was created.
+    pub fn frame_count(&self) -> u64 {
+        self.cpu.frames
+    }
+
+    /// Returns the framebuffer.
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.cpu.pixels
+    }
+
+    /// Returns whether the ROM exited with 00FD.
+    pub fn is_halted(&self) -> bool {
+        self.cpu.halted
+    }
+
+    /// Returns whether the program counter is at a 1NNN jump to itself, the
+    /// usual way for a test ROM to end.
+    pub fn is_spinning(&self) -> bool {
+        let pc = self.cpu.pc;
+        let instruction = self
+            .cpu
+            .opcode_at(pc as usize)
+            .map(|opcode| decode(opcode, None));
+        instruction == Ok(Ok(Instruction::JumpToAddress { nnn: pc }))
+    }
+
+    /// Returns the SUPER-CHIP RPL user flags saved by FX75.
+    pub fn rpl_flags(&self) -> &[u8; 16] {
+        &self.cpu.rpl
+    }
+
+    /// Sets the SUPER-CHIP RPL user flags loaded by FX85.
+    pub fn set_rpl_flags(&mut self, flags: [u8; 16]) {
+        self.cpu.rpl = flags;
+    }
+
+    /// Presses or releases key `key` (0x0 through 0xF).
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        self.cpu.keys[(key & 0xF) as usize] = pressed;
+    }
+
+    /// Presses key `key` (0x0 through 0xF).
+    pub fn press_key(&mut self, key: u8) {
+        self.set_key(key, true);
+    }
+
+    /// Releases key `key` (0x0 through 0xF).
+    pub fn release_key(&mut self, key: u8) {
+        self.set_key(key, false);
+    }
+
+    /// Returns whether key `key` (0x0 through 0xF) is held down.
+    pub fn is_key_pressed(&self, key: u8) -> bool {
+        self.cpu.keys[(key & 0xF) as usize]
+    }
+
+    /// Returns the state of all 16 keys, indexed by key value.
+    pub fn keypad(&self) -> &[bool; 16] {
+        &self.cpu.keys
+    }
+
+    /// Returns register V`index` (0x0 through 0xF).
+    pub fn v(&self, index: u8) -> u8 {
+        self.cpu.v[(index & 0xF) as usize]
+    }
+
+    /// Sets register V`index` (0x0 through 0xF).
+    pub fn set_v(&mut self, index: u8, value: u8) {
+        self.cpu.v[(index & 0xF) as usize] = value;
+    }
+
+    /// Returns the index register.
+    pub fn i(&self) -> u16 {
+        self.cpu.i
+    }
+
+    /// Sets the index register.
+    pub fn set_i(&mut self, value: u16) {
+        self.cpu.i = value;
+    }
+
+    /// Returns the program counter.
+    pub fn pc(&self) -> u16 {
+        self.cpu.pc
+    }
+
+    /// Sets the program counter.
+    pub fn set_pc(&mut self, value: u16) {
+        self.cpu.pc = value;
+    }
+
+    /// Returns the stack pointer, the number of addresses on the stack.
+    pub fn sp(&self) -> u8 {
+        self.cpu.sp
+    }
+
+    /// Returns the return addresses on the stack, oldest first.
+    ///
+    /// A memory-resident stack is read from memory, so entries that the ROM
+    /// overwrote show up as it will see them when returning. Fails when an
+    /// entry lies outside memory, as returning to it would.
+    pub fn stack(&self) -> Result<Vec<u16>, Chip8Error> {
+        (0..self.cpu.sp as usize)
+            .map(|index| self.cpu.stack_entry(index))
+            .collect::<Result<_, _>>()
+            .map_err(|fault| fault.at(self.cpu.pc, 0))
+    }
+
+    /// Returns the delay timer.
+    pub fn delay_timer(&self) -> u8 {
+        self.cpu.delay_timer
+    }
+
+    /// Sets the delay timer.
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.cpu.delay_timer = value;
+    }
+
+    /// Returns the sound timer.
+    pub fn sound_timer(&self) -> u8 {
+        self.cpu.sound_timer
+    }
+
+    /// Sets the sound timer.
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.cpu.sound_timer = value;
+    }
+
+    /// Returns the whole address space.
+    pub fn memory(&self) -> &[u8] {
+        &self.cpu.memory
+    }
+
+    /// Returns the options the machine was created with.
+    pub fn options(&self) -> &Options {
+        &self.cpu.options
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::Platform;
+
+    fn machine_at(pc: u16) -> Chip8 {
+        let mut chip8 = Chip8::headless(Options::default()).unwrap();
+        chip8.set_pc(pc);
+        chip8
+    }
+
+    #[test]
+    fn executed_fx0a_blocks_at_the_program_counter() {
+        let mut chip8 = machine_at(0x300);
+        chip8.execute_instruction(0xF00A).unwrap();
+        assert_eq!(chip8.pc(), 0x300);
+
+        chip8.press_key(4);
+        chip8.execute_instruction(0xF00A).unwrap();
+        assert_eq!(chip8.pc(), 0x302);
+        assert_eq!(chip8.v(0), 4);
+    }
+
+    #[test]
+    fn executed_call_returns_to_the_next_instruction() {
+        let mut chip8 = machine_at(0x300);
+        chip8.execute_instruction(0x2400).unwrap();
+        assert_eq!(chip8.pc(), 0x400);
+        assert_eq!(chip8.stack(), Ok(vec![0x302]));
+
+        chip8.execute_instruction(0x00EE).unwrap();
+        assert_eq!(chip8.pc(), 0x302);
+        assert_eq!(chip8.sp(), 0);
+    }
+
+    #[test]
+    fn executed_errors_report_the_program_counter() {
+        let mut chip8 = machine_at(0x300);
+        assert_eq!(
+            chip8.execute_instruction(0x5001),
+            Err(Chip8Error::UnknownOpcode {
+                pc: 0x300,
+                opcode: 0x5001
+            })
+        );
+    }
+
+    #[test]
+    fn runs_later_0nnn_opcodes_as_machine_calls() {
+        let mut chip8 = Chip8::headless(Platform::Chip48.options()).unwrap();
+        chip8.set_pc(0x300);
+        for opcode in [0x00C5, 0x00D1, 0x00FB, 0x00FD, 0x00FF] {
+            assert_eq!(chip8.execute_instruction(opcode), Ok(()), "{:04X}", opcode);
+        }
+        assert_eq!(chip8.pc(), 0x30A);
+        assert!(!chip8.framebuffer().is_hires());
+    }
+
+    #[test]
+    fn rejects_fonts_overlapping_the_rom() {
+        let options = Options {
+            font_address: 0x1F0,
+            ..Options::default()
+        };
+        assert_eq!(
+            Chip8::headless(options).err(),
+            Some(Chip8Error::InvalidFontAddress { address: 0x1F0 })
+        );
+    }
+}
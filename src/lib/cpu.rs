@@ -1,35 +1,121 @@
-use crate::lib::font::FONT;
-use crate::lib::graphics::{Display, HEIGHT, WIDTH};
-use crate::lib::ops;
+use crate::audio::{Audio, NullAudio};
+use crate::error::{Chip8Error, Fault};
+use crate::font::{FontSet, FONTS_SIZE};
+use crate::graphics::{Framebuffer, Video, FIRST_PLANE};
+use crate::instruction::{decode_for, Instruction};
+use crate::ops;
+use crate::platform::{InstructionSet, Platform};
+use crate::rng::Rng;
+use crate::storage::{rom_hash, FlagStorage, NullStorage};
+use std::ops::Range;
 
+// Frames emulated per second, the timers tick once per frame
+pub const FRAME_RATE: u32 = 60;
+
+// Instructions executed per 60 Hz frame unless configured otherwise
+pub const DEFAULT_CYCLES_PER_FRAME: u32 = 10;
+
+// Most nested subroutine calls any interpreter allows
+pub const MAX_STACK_DEPTH: usize = 16;
+
+// Quirks that differ between interpreters, see `Platform` for complete sets
+#[derive(Clone, Copy, Debug)]
 pub struct Options {
-    pub put_value_of_vy_into_vx_before_shifting: bool,
-    pub jump_to_nnn_plus_the_value_in_v0: bool,
-    pub increment_i_when_storing_loading_memory: bool,
+    pub put_value_of_vy_into_vx_before_shifting: bool, // 8XY6/8XYE shift VY into VX
+    pub jump_to_nnn_plus_the_value_in_v0: bool,        // BNNN jumps to NNN + V0, not XNN + VX
+    pub increment_i_when_storing_loading_memory: bool, // FX55/FX65 leave I past VX
+    pub wait_for_key_release: bool,                    // FX0A continues on key release
+    pub set_vf_on_i_overflow: bool,                    // FX1E sets VF when I passes 0xFFF
+    pub reset_vf_on_logic_ops: bool,                   // 8XY1/8XY2/8XY3 reset VF to 0
+    pub clip_sprites: bool,                            // DXYN clips instead of wraps at the edges
+    pub wait_for_vblank: bool,                         // DXYN waits for the next frame
+    pub stack_depth: u8,                               // Nested calls before 2NNN overflows
+    pub stack_address: Option<u16>,                    // Keep the stack in memory, down from here
+    pub font: FontSet,                                 // Glyphs of the small font
+    pub font_address: u16,                             // Small font, then the large font
+    pub instruction_set: InstructionSet,               // Instructions beyond CHIP-8
+    pub count_collided_rows: bool,                     // Hires DXYN sets VF to the collided rows
+}
+
+impl Options {
+    // Checks the options a machine can not run with, the fonts have to fit
+    // below the ROM at 0x200 and the stack has to hold 1 to 16 addresses
+    pub fn validate(&self) -> Result<(), Chip8Error> {
+        if !(1..=MAX_STACK_DEPTH).contains(&(self.stack_depth as usize)) {
+            return Err(Chip8Error::InvalidStackDepth {
+                depth: self.stack_depth,
+            });
+        }
+        if self.font_address as usize + FONTS_SIZE as usize > 0x200 {
+            return Err(Chip8Error::InvalidFontAddress {
+                address: self.font_address,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Platform::default().options()
+    }
 }
 
-pub struct CPU {
-    pub memory: [u8; 0x1000],            // RAM (4KiB)
-    pub pc: u16,                         // Program counter
-    pub delay_timer: u8,                 // Delay timer
-    pub sound_timer: u8,                 // Sound timer
-    pub stack: Vec<u16>,                 // Stack
-    pub sp: u8,                          // Stack pointer
-    pub i: u16,                          // Index register
-    pub v: [u8; 16],                     // General purpose registers (V0 through VF)
-    pub pixels: [[bool; WIDTH]; HEIGHT], // Display (64 x 32)
-    pub display: Display,                // Display
-    pub options: Options,                // Extra options for compatibility
+// Like the COSMAC VIP, the memory-resident stack grows down from `top`: each
+// call stores the low byte of the address at the stack pointer and the high
+// byte below it, so entries read big-endian
+fn stack_entry_address(top: u16, index: usize) -> Result<usize, Fault> {
+    (top as usize)
+        .checked_sub(index * 2 + 1)
+        .ok_or(Fault::OutOfBounds(0))
+}
+
+#[allow(clippy::upper_case_acronyms)]
+pub(crate) struct CPU {
+    pub(crate) memory: Vec<u8>,               // RAM (4KiB, 64KiB on XO-CHIP)
+    pub(crate) pc: u16,                       // Program counter
+    pub(crate) delay_timer: u8,               // Delay timer
+    pub(crate) sound_timer: u8,               // Sound timer
+    pub(crate) stack: [u16; MAX_STACK_DEPTH], // Stack
+    pub(crate) sp: u8,                        // Stack pointer (number of entries in use)
+    pub(crate) i: u16,                        // Index register
+    pub(crate) v: [u8; 16],                   // General purpose registers (V0 through VF)
+    pub(crate) keys: [bool; 16],              // Keypad state (0x0 through 0xF)
+    pub(crate) waiting_key: Option<u8>,       // Key pressed during FX0A, awaiting release
+    pub(crate) held_keys: Option<[bool; 16]>, // Keys down since FX0A started waiting
+    pub(crate) pixels: Framebuffer,           // Display (64 x 32 or 128 x 64)
+    pub(crate) planes: u8,                    // XO-CHIP bitplanes selected for drawing
+    pub(crate) pattern: Option<[u8; 16]>,     // XO-CHIP audio pattern, loaded by F002
+    pub(crate) pitch: u8,                     // XO-CHIP audio pattern pitch
+    pub(crate) rpl: [u8; 16],                 // SUPER-CHIP RPL user flags
+    pub(crate) halted: bool,                  // Exited with 00FD
+    pub(crate) display: Box<dyn Video>,       // Video sink the framebuffer is presented to
+    pub(crate) audio: Box<dyn Audio>,         // Audio sink the buzzer is played on
+    pub(crate) flags: Box<dyn FlagStorage>,   // Where FX75 saves the RPL user flags
+    pub(crate) options: Options,              // Extra options for compatibility
+    pub(crate) cycles_per_frame: u32,         // Instructions per 60 Hz frame
+    pub(crate) frame_cycle: u32,              // Instructions executed in the current frame
+    pub(crate) waiting_for_vblank: bool,      // CPU idles until the next frame
+    pub(crate) frames: u64,                   // Frames emulated since power on
+    pub(crate) rom_hash: u64,                 // Identifies the ROM in save states
+    pub(crate) rng: Rng,                      // Random number source of CXNN
 }
 
 impl CPU {
-    pub fn new(display: Display, options: Options) -> CPU {
+    pub fn new(display: Box<dyn Video>, options: Options) -> CPU {
         // Initialize memory
-        let mut memory = [0; 0x1000];
+        let mut memory = vec![0; options.instruction_set.memory_size()];
 
-        // Load font into memory at address 0x050 -> 0x09F
-        for i in 0x050..0x09F {
-            memory[i] = FONT[i - 0x050];
+        // Load the small font followed by the large font into memory, by
+        // default at address 0x050 -> 0x09F and 0x0A0 -> 0x13F
+        let fonts = options
+            .font
+            .small_font()
+            .iter()
+            .chain(options.font.large_font());
+        let start = options.font_address as usize;
+        for (byte, glyph_byte) in memory.iter_mut().skip(start).zip(fonts) {
+            *byte = *glyph_byte;
         }
 
         // Initialize the CPU with default values
@@ -38,100 +124,261 @@ impl CPU {
             pc: 0x200,
             delay_timer: 0,
             sound_timer: 0,
-            stack: Vec::new(),
+            stack: [0; MAX_STACK_DEPTH],
             sp: 0,
             i: 0,
             v: [0; 16],
-            pixels: [[false; WIDTH]; HEIGHT],
+            keys: [false; 16],
+            waiting_key: None,
+            held_keys: None,
+            pixels: Framebuffer::new(),
+            planes: FIRST_PLANE,
+            pattern: None,
+            pitch: 64,
+            rpl: [0; 16],
+            halted: false,
             display,
+            audio: Box::new(NullAudio),
+            flags: Box::new(NullStorage),
             options,
+            cycles_per_frame: DEFAULT_CYCLES_PER_FRAME,
+            frame_cycle: 0,
+            waiting_for_vblank: false,
+            frames: 0,
+            rom_hash: rom_hash(&[]),
+            rng: Rng::new(0),
         }
     }
-    pub fn load_rom(&mut self, rom: Vec<u8>) {
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), Chip8Error> {
         // Load the ROM into memory starting at 0x200
-        let rom_length = rom.len();
-        for i in 0..rom_length {
-            self.memory[0x200 + i] = rom[i];
+        let max = self.memory.len() - 0x200;
+        if rom.len() > max {
+            return Err(Chip8Error::RomTooLarge {
+                size: rom.len(),
+                max,
+            });
+        }
+        self.memory[0x200..0x200 + rom.len()].copy_from_slice(rom);
+        self.rom_hash = rom_hash(rom);
+        Ok(())
+    }
+    pub fn push(&mut self, address: u16) -> Result<(), Fault> {
+        if self.sp >= self.options.stack_depth {
+            return Err(Fault::StackOverflow);
+        }
+        self.set_stack_entry(self.sp as usize, address)?;
+        self.sp += 1;
+        Ok(())
+    }
+    pub fn pop(&mut self) -> Result<u16, Fault> {
+        if self.sp == 0 {
+            return Err(Fault::StackUnderflow);
+        }
+        // Read before popping, so a fault leaves the stack as it was
+        let address = self.stack_entry(self.sp as usize - 1)?;
+        self.sp -= 1;
+        Ok(address)
+    }
+    pub fn stack_entry(&self, index: usize) -> Result<u16, Fault> {
+        match self.options.stack_address {
+            Some(top) => {
+                let address = stack_entry_address(top, index)?;
+                Ok((self.read(address)? as u16) << 8 | self.read(address + 1)? as u16)
+            }
+            None => Ok(self.stack[index]),
+        }
+    }
+    fn set_stack_entry(&mut self, index: usize, value: u16) -> Result<(), Fault> {
+        match self.options.stack_address {
+            Some(top) => {
+                let address = stack_entry_address(top, index)?;
+                self.write(address, (value >> 8) as u8)?;
+                self.write(address + 1, value as u8)
+            }
+            None => {
+                self.stack[index] = value;
+                Ok(())
+            }
         }
     }
-    pub fn step(&mut self) {
-        // Fetch instruction that the PC is currently pointing to from memory
-        let first_opcode_byte = self.memory[self.pc as usize];
-        let second_opcode_byte = self.memory[self.pc as usize + 1];
-        let opcode = (first_opcode_byte as u16) << 8 | second_opcode_byte as u16;
-
-        // Increment PC
-        self.pc += 2;
-
-        // Execute instruction
-        self.execute_instruction(opcode);
-    }
-    pub fn execute_instruction(&mut self, opcode: u16) {
-        /*
-        | nnn = 0000NNNN NNNNNNNN | low byte + lower 4 bits of high byte
-        |  nn = 00000000 NNNNNNNN | low byte
-        |   n = 00000000 0000NNNN | lower 4 bits of low byte
-        |   x = 0000XXXX 00000000 | lower 4 bits of high byte
-        |   y = 00000000 YYYY0000 | upper 4 bits of low byte
-        */
-        let nnn = opcode & 0x0FFF;
-        let nn = (opcode & 0x00FF) as u8;
-        let n = (opcode & 0x000F) as u8;
-        let x = ((opcode & 0x0F00) >> 8) as u8;
-        let y = ((opcode & 0x00F0) >> 4) as u8;
-
-        // Print opcode in hex
-        println!("{:#02X}", opcode);
-
-        match opcode >> 12 {
-            0x0 => match nn {
-                0x00 => (),
-                0xE0 => ops::clear_screen(self),
-                0xEE => ops::return_from_subroutine(self),
-                _ => ops::unknown_opcode(opcode),
-            },
-            0x1 => ops::jump_to_address(self, nnn),
-            0x2 => ops::call_subroutine(self, nnn),
-            0x3 => ops::skip_next_if_vx_equals_nn(self, x, nn),
-            0x4 => ops::skip_next_if_vx_not_equals_nn(self, x, nn),
-            0x5 => ops::skip_next_if_vx_equals_vy(self, x, y),
-            0x6 => ops::set_vx_to_nn(self, x, nn),
-            0x7 => ops::add_nn_to_vx(self, x, nn),
-            0x8 => match n {
-                0x0 => ops::set_vx_to_vy(self, x, y),
-                0x1 => ops::set_vx_to_vx_or_vy(self, x, y),
-                0x2 => ops::set_vx_to_vx_and_vy(self, x, y),
-                0x3 => ops::set_vx_to_vx_xor_vy(self, x, y),
-                0x4 => ops::add_vy_to_vx(self, x, y),
-                0x5 => ops::set_vx_to_vx_minus_vy(self, x, y),
-                0x6 => ops::shift_vx_right_by_one(self, x, y),
-                0x7 => ops::set_vx_to_vy_minus_vx(self, x, y),
-                0xE => ops::shift_vx_left_by_one(self, x, y),
-                _ => ops::unknown_opcode(opcode),
-            },
-            0x9 => ops::skip_next_if_vx_not_equals_vy(self, x, y),
-            0xA => ops::set_i_to_nnn(self, nnn),
-            0xB => ops::jump_to_address_plus_v0(self, x, nnn),
-            0xC => ops::set_vx_to_random_number_and_nn(self, x, nn),
-            0xD => ops::draw_sprite(self, x, y, n),
-            0xE => match nn {
-                0x9E => ops::skip_next_if_key_is_pressed(self, x),
-                0xA1 => ops::skip_next_if_key_is_not_pressed(self, x),
-                _ => ops::unknown_opcode(opcode),
-            },
-            0xF => match nn {
-                0x07 => ops::set_vx_to_delay_timer(self, x),
-                0x0A => ops::wait_for_keypress(self, x),
-                0x15 => ops::set_delay_timer_to_vx(self, x),
-                0x18 => ops::set_sound_timer_to_vx(self, x),
-                0x1E => ops::add_vx_to_i(self, x),
-                0x29 => ops::set_i_to_sprite_location(self, x),
-                0x33 => ops::set_bcd_of_vx_at_i(self, x),
-                0x55 => ops::store_registers_in_memory(self, x),
-                0x65 => ops::load_registers_from_memory(self, x),
-                _ => ops::unknown_opcode(opcode),
-            },
-            _ => ops::unknown_opcode(opcode),
+    pub fn skip_next(&mut self) {
+        // XO-CHIP skips both words of the F000 NNNN long load
+        let pc = self.pc as usize;
+        let next = self.opcode_at(pc + 2).ok();
+        let instruction_set = self.options.instruction_set;
+        let size = match self
+            .opcode_at(pc)
+            .map(|opcode| decode_for(opcode, next, instruction_set))
+        {
+            Ok(Ok(instruction)) => instruction.size(),
+            _ => 2,
+        };
+        self.pc = self.pc.wrapping_add(size);
+    }
+    pub fn opcode_at(&self, address: usize) -> Result<u16, Fault> {
+        Ok((self.read(address)? as u16) << 8 | self.read(address + 1)? as u16)
+    }
+    pub fn read(&self, address: usize) -> Result<u8, Fault> {
+        self.memory
+            .get(address)
+            .copied()
+            .ok_or(Fault::OutOfBounds(address))
+    }
+    pub fn write(&mut self, address: usize, value: u8) -> Result<(), Fault> {
+        let byte = self
+            .memory
+            .get_mut(address)
+            .ok_or(Fault::OutOfBounds(address))?;
+        *byte = value;
+        Ok(())
+    }
+    pub fn memory_range(&self, address: usize, length: usize) -> Result<Range<usize>, Fault> {
+        // The first address past the end of memory is the one that faults
+        if address + length > self.memory.len() {
+            return Err(Fault::OutOfBounds(address.max(self.memory.len())));
+        }
+        Ok(address..address + length)
+    }
+    pub fn step(&mut self) -> Result<(), Chip8Error> {
+        // A halted CPU only lets time pass
+        if !self.halted {
+            // Fetch instruction that the PC is currently pointing to from memory
+            let pc = self.pc;
+            let opcode = self
+                .opcode_at(pc as usize)
+                .map_err(|fault| fault.at(pc, 0))?;
+
+            // Increment PC
+            self.pc = self.pc.wrapping_add(2);
+
+            // Execute instruction
+            self.execute_instruction(opcode)?;
+        }
+
+        // Emulated time advances by one cycle, the timers tick once every frame.
+        // A CPU waiting for the vertical blank skips ahead to the next frame.
+        self.frame_cycle += 1;
+        if self.frame_cycle >= self.cycles_per_frame || self.waiting_for_vblank {
+            self.end_frame();
+        }
+        Ok(())
+    }
+    pub fn run_frame(&mut self) -> Result<(), Chip8Error> {
+        // Execute the remaining instructions of the current frame
+        let frames = self.frames;
+        while self.frames == frames {
+            self.step()?;
+        }
+        Ok(())
+    }
+    pub fn end_frame(&mut self) {
+        // Present the framebuffer once per frame
+        self.display.draw(&self.pixels);
+
+        // The buzzer sounds for as long as the sound timer is active
+        self.audio.update(self.sound_timer > 0);
+        self.tick_timers();
+        self.frame_cycle = 0;
+        self.waiting_for_vblank = false;
+        self.frames += 1;
+    }
+    pub fn tick_timers(&mut self) {
+        // Both timers count down at 60 Hz until they reach zero
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+    }
+    pub fn execute_instruction(&mut self, opcode: u16) -> Result<(), Chip8Error> {
+        // The PC has already moved past the instruction
+        let pc = self.pc.wrapping_sub(2);
+
+        // F000 NNNN also takes the word after it, where the PC is now
+        let next = self.opcode_at(self.pc as usize);
+
+        // SUPER-CHIP and XO-CHIP instructions are unknown to plain CHIP-8,
+        // apart from the 0NNN ones that it runs as machine code routines
+        let result = match decode_for(opcode, next.ok(), self.options.instruction_set) {
+            Ok(instruction) => self.execute(instruction),
+            Err(_) if opcode == 0xF000 => next.and_then(|_| ops::unknown_opcode()),
+            Err(_) => ops::unknown_opcode(),
+        };
+
+        result.map_err(|fault| fault.at(pc, opcode))
+    }
+    pub fn execute(&mut self, instruction: Instruction) -> Result<(), Fault> {
+        match instruction {
+            Instruction::MachineCall { .. } => Ok(()),
+            Instruction::ScrollDown { n } => ops::scroll_down(self, n),
+            Instruction::ScrollUp { n } => ops::scroll_up(self, n),
+            Instruction::ClearScreen => ops::clear_screen(self),
+            Instruction::ReturnFromSubroutine => ops::return_from_subroutine(self),
+            Instruction::ScrollRight => ops::scroll_right(self),
+            Instruction::ScrollLeft => ops::scroll_left(self),
+            Instruction::Exit => ops::exit(self),
+            Instruction::LowResolution => ops::set_resolution(self, false),
+            Instruction::HighResolution => ops::set_resolution(self, true),
+            Instruction::JumpToAddress { nnn } => ops::jump_to_address(self, nnn),
+            Instruction::CallSubroutine { nnn } => ops::call_subroutine(self, nnn),
+            Instruction::SkipNextIfVxEqualsNn { x, nn } => {
+                ops::skip_next_if_vx_equals_nn(self, x, nn)
+            }
+            Instruction::SkipNextIfVxNotEqualsNn { x, nn } => {
+                ops::skip_next_if_vx_not_equals_nn(self, x, nn)
+            }
+            Instruction::SkipNextIfVxEqualsVy { x, y } => {
+                ops::skip_next_if_vx_equals_vy(self, x, y)
+            }
+            Instruction::StoreVxToVyInMemory { x, y } => ops::store_vx_to_vy_in_memory(self, x, y),
+            Instruction::LoadVxToVyFromMemory { x, y } => {
+                ops::load_vx_to_vy_from_memory(self, x, y)
+            }
+            Instruction::SetVxToNn { x, nn } => ops::set_vx_to_nn(self, x, nn),
+            Instruction::AddNnToVx { x, nn } => ops::add_nn_to_vx(self, x, nn),
+            Instruction::SetVxToVy { x, y } => ops::set_vx_to_vy(self, x, y),
+            Instruction::SetVxToVxOrVy { x, y } => ops::set_vx_to_vx_or_vy(self, x, y),
+            Instruction::SetVxToVxAndVy { x, y } => ops::set_vx_to_vx_and_vy(self, x, y),
+            Instruction::SetVxToVxXorVy { x, y } => ops::set_vx_to_vx_xor_vy(self, x, y),
+            Instruction::AddVyToVx { x, y } => ops::add_vy_to_vx(self, x, y),
+            Instruction::SetVxToVxMinusVy { x, y } => ops::set_vx_to_vx_minus_vy(self, x, y),
+            Instruction::ShiftVxRightByOne { x, y } => ops::shift_vx_right_by_one(self, x, y),
+            Instruction::SetVxToVyMinusVx { x, y } => ops::set_vx_to_vy_minus_vx(self, x, y),
+            Instruction::ShiftVxLeftByOne { x, y } => ops::shift_vx_left_by_one(self, x, y),
+            Instruction::SkipNextIfVxNotEqualsVy { x, y } => {
+                ops::skip_next_if_vx_not_equals_vy(self, x, y)
+            }
+            Instruction::SetIToNnn { nnn } => ops::set_i_to_nnn(self, nnn),
+            Instruction::JumpToAddressPlusV0 { x, nnn } => {
+                ops::jump_to_address_plus_v0(self, x, nnn)
+            }
+            Instruction::SetVxToRandomNumberAndNn { x, nn } => {
+                ops::set_vx_to_random_number_and_nn(self, x, nn)
+            }
+            Instruction::DrawSprite { x, y, n } => ops::draw_sprite(self, x, y, n),
+            Instruction::SkipNextIfKeyIsPressed { x } => ops::skip_next_if_key_is_pressed(self, x),
+            Instruction::SkipNextIfKeyIsNotPressed { x } => {
+                ops::skip_next_if_key_is_not_pressed(self, x)
+            }
+            Instruction::SetIToLongAddress { nnnn } => ops::set_i_to_long_address(self, nnnn),
+            Instruction::SelectPlanes { x } => ops::select_planes(self, x),
+            Instruction::LoadPattern => ops::load_pattern(self),
+            Instruction::SetVxToDelayTimer { x } => ops::set_vx_to_delay_timer(self, x),
+            Instruction::WaitForKeypress { x } => ops::wait_for_keypress(self, x),
+            Instruction::SetDelayTimerToVx { x } => ops::set_delay_timer_to_vx(self, x),
+            Instruction::SetSoundTimerToVx { x } => ops::set_sound_timer_to_vx(self, x),
+            Instruction::AddVxToI { x } => ops::add_vx_to_i(self, x),
+            Instruction::SetIToSpriteLocation { x } => ops::set_i_to_sprite_location(self, x),
+            Instruction::SetIToLargeSpriteLocation { x } => {
+                ops::set_i_to_large_sprite_location(self, x)
+            }
+            Instruction::SetBcdOfVxAtI { x } => ops::set_bcd_of_vx_at_i(self, x),
+            Instruction::SetPitchToVx { x } => ops::set_pitch_to_vx(self, x),
+            Instruction::StoreRegistersInMemory { x } => ops::store_registers_in_memory(self, x),
+            Instruction::LoadRegistersFromMemory { x } => ops::load_registers_from_memory(self, x),
+            Instruction::StoreRegistersInRplFlags { x } => {
+                ops::store_registers_in_rpl_flags(self, x)
+            }
+            Instruction::LoadRegistersFromRplFlags { x } => {
+                ops::load_registers_from_rpl_flags(self, x)
+            }
         }
     }
 }
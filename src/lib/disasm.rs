@@ -0,0 +1,496 @@
+use crate::instruction::{decode_for, Instruction};
+use crate::platform::InstructionSet;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::str::FromStr;
+
+// Address the ROM is loaded at and execution starts from
+const ROM_START: usize = 0x200;
+
+// Most data bytes printed on one line
+const DATA_PER_LINE: usize = 8;
+
+// Assembly syntaxes the disassembler can write
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Syntax {
+    #[default]
+    Cowgod, // Mnemonics of Cowgod's technical reference, as in `ops`
+    Octo, // Statements of the Octo assembler
+}
+
+impl FromStr for Syntax {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Syntax, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "cowgod" => Ok(Syntax::Cowgod),
+            "octo" => Ok(Syntax::Octo),
+            _ => Err(format!("Unknown syntax: {}", s)),
+        }
+    }
+}
+
+// What a label marks, which decides its name
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelKind {
+    Subroutine, // Target of 2NNN
+    Jump,       // Target of 1NNN or BNNN
+    Data,       // Target of ANNN or F000 NNNN
+}
+
+/*
+|  Disassembles `rom` as loaded at 0x200, one line per instruction with its
+|  address, raw bytes and mnemonic. Code is told apart from data by following
+|  the control flow from 0x200: jumps, calls and both ways of every skip are
+|  followed, while returns, exits, computed jumps (BNNN) and opcodes that
+|  `instruction_set` does not have end a path. SYS (0NNN) is ignored by the
+|  interpreters, so it falls through like any other instruction. Bytes never
+|  reached are printed as data. Targets of jumps, calls and I loads inside
+|  the ROM get labels, which the operands refer to.
+*/
+pub fn disassemble(rom: &[u8], instruction_set: InstructionSet, syntax: Syntax) -> String {
+    let code = trace(rom, instruction_set);
+
+    // Only addresses that start a line can carry a label
+    let mut labels = BTreeMap::new();
+    for &instruction in code.values() {
+        let target = match instruction {
+            Instruction::CallSubroutine { nnn } => Some((nnn as usize, LabelKind::Subroutine)),
+            Instruction::JumpToAddress { nnn } | Instruction::JumpToAddressPlusV0 { nnn, .. } => {
+                Some((nnn as usize, LabelKind::Jump))
+            }
+            Instruction::SetIToNnn { nnn } => Some((nnn as usize, LabelKind::Data)),
+            Instruction::SetIToLongAddress { nnnn } => Some((nnnn as usize, LabelKind::Data)),
+            _ => None,
+        };
+        if let Some((target, kind)) = target {
+            let in_rom = target >= ROM_START && target < ROM_START + rom.len();
+            let inside_instruction = code
+                .range(..target)
+                .next_back()
+                .is_some_and(|(&start, instruction)| start + instruction.size() as usize > target);
+            if in_rom && !inside_instruction {
+                // A subroutine that is also jumped to is still a subroutine
+                let entry = labels.entry(target).or_insert(kind);
+                *entry = (*entry).min(kind);
+            }
+        }
+    }
+    let labels: BTreeMap<usize, String> = labels
+        .into_iter()
+        .map(|(address, kind)| (address, label_name(address, kind)))
+        .collect();
+
+    let mut text = String::new();
+    let mut address = ROM_START;
+    while address < ROM_START + rom.len() {
+        if let Some(label) = labels.get(&address) {
+            let _ = match syntax {
+                Syntax::Cowgod => writeln!(text, "{}:", label),
+                Syntax::Octo => writeln!(text, ": {}", label),
+            };
+        }
+
+        let (size, mnemonic) = match code.get(&address) {
+            Some(&instruction) => {
+                let operands = Operands {
+                    syntax,
+                    labels: &labels,
+                };
+                (instruction.size() as usize, operands.mnemonic(instruction))
+            }
+            None => {
+                // Data runs up to the next code or label
+                let mut size = 1;
+                while size < DATA_PER_LINE
+                    && address + size < ROM_START + rom.len()
+                    && !code.contains_key(&(address + size))
+                    && !labels.contains_key(&(address + size))
+                {
+                    size += 1;
+                }
+                let bytes = &rom[address - ROM_START..address - ROM_START + size];
+                (size, data(bytes, syntax))
+            }
+        };
+
+        let bytes: Vec<String> = rom[address - ROM_START..address - ROM_START + size]
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        let _ = writeln!(
+            text,
+            "{:04X}  {:<23}  {}",
+            address,
+            bytes.join(" "),
+            mnemonic
+        );
+        address += size;
+    }
+    text
+}
+
+// Follows the control flow from 0x200, returns every instruction reached by
+// its address
+fn trace(rom: &[u8], instruction_set: InstructionSet) -> BTreeMap<usize, Instruction> {
+    let word = |address: usize| -> Option<u16> {
+        let offset = address.checked_sub(ROM_START)?;
+        let bytes = rom.get(offset..offset + 2)?;
+        Some((bytes[0] as u16) << 8 | bytes[1] as u16)
+    };
+    let instruction_at = |address: usize| -> Option<Instruction> {
+        decode_for(word(address)?, word(address + 2), instruction_set).ok()
+    };
+
+    let mut code: BTreeMap<usize, Instruction> = BTreeMap::new();
+    let mut covered = BTreeSet::new();
+    let mut pending = vec![ROM_START];
+    while let Some(address) = pending.pop() {
+        if code.contains_key(&address) || covered.contains(&address) {
+            continue;
+        }
+        let instruction = match instruction_at(address) {
+            Some(found) => found,
+            None => continue,
+        };
+
+        // Instructions that overlap ones already found end the path
+        let size = instruction.size() as usize;
+        if (address + 1..address + size).any(|byte| code.contains_key(&byte)) {
+            continue;
+        }
+        covered.extend(address + 1..address + size);
+        code.insert(address, instruction);
+
+        let next = address + size;
+        match instruction {
+            Instruction::JumpToAddress { nnn } => pending.push(nnn as usize),
+            Instruction::CallSubroutine { nnn } => {
+                pending.push(next);
+                pending.push(nnn as usize);
+            }
+            Instruction::ReturnFromSubroutine
+            | Instruction::Exit
+            | Instruction::JumpToAddressPlusV0 { .. } => {}
+            // Machine code routines are never run, the path goes on after them
+            Instruction::MachineCall { .. } => pending.push(next),
+            Instruction::SkipNextIfVxEqualsNn { .. }
+            | Instruction::SkipNextIfVxNotEqualsNn { .. }
+            | Instruction::SkipNextIfVxEqualsVy { .. }
+            | Instruction::SkipNextIfVxNotEqualsVy { .. }
+            | Instruction::SkipNextIfKeyIsPressed { .. }
+            | Instruction::SkipNextIfKeyIsNotPressed { .. } => {
+                // A skip passes over both words of F000 NNNN
+                let skipped = instruction_at(next).map_or(2, |skipped| skipped.size());
+                pending.push(next + skipped as usize);
+                pending.push(next);
+            }
+            _ => pending.push(next),
+        }
+    }
+    code
+}
+
+fn label_name(address: usize, kind: LabelKind) -> String {
+    let prefix = match kind {
+        LabelKind::Subroutine => "sub",
+        LabelKind::Jump => "label",
+        LabelKind::Data => "data",
+    };
+    format!("{}_{:03X}", prefix, address)
+}
+
+fn data(bytes: &[u8], syntax: Syntax) -> String {
+    let bytes: Vec<String> = bytes
+        .iter()
+        .map(|byte| match syntax {
+            Syntax::Cowgod => format!("#{:02X}", byte),
+            Syntax::Octo => format!("0x{:02X}", byte),
+        })
+        .collect();
+    match syntax {
+        Syntax::Cowgod => format!("DB {}", bytes.join(", ")),
+        Syntax::Octo => bytes.join(" "),
+    }
+}
+
+// Writes the operands of an instruction in one syntax
+struct Operands<'a> {
+    syntax: Syntax,
+    labels: &'a BTreeMap<usize, String>,
+}
+
+impl Operands<'_> {
+    fn v(&self, x: u8) -> String {
+        match self.syntax {
+            Syntax::Cowgod => format!("V{:X}", x),
+            Syntax::Octo => format!("v{:x}", x),
+        }
+    }
+
+    fn byte(&self, nn: u8) -> String {
+        match self.syntax {
+            Syntax::Cowgod => format!("#{:02X}", nn),
+            Syntax::Octo => format!("0x{:02X}", nn),
+        }
+    }
+
+    fn nibble(&self, n: u8) -> String {
+        match self.syntax {
+            Syntax::Cowgod => format!("#{:X}", n),
+            Syntax::Octo => n.to_string(),
+        }
+    }
+
+    // The label of an address, or the address itself
+    fn address(&self, nnn: u16) -> String {
+        match (self.labels.get(&(nnn as usize)), self.syntax) {
+            (Some(label), _) => label.clone(),
+            (None, Syntax::Cowgod) => format!("#{:03X}", nnn),
+            (None, Syntax::Octo) => format!("0x{:03X}", nnn),
+        }
+    }
+
+    fn mnemonic(&self, instruction: Instruction) -> String {
+        match self.syntax {
+            Syntax::Cowgod => self.cowgod(instruction),
+            Syntax::Octo => self.octo(instruction),
+        }
+    }
+
+    fn cowgod(&self, instruction: Instruction) -> String {
+        let v = |x| self.v(x);
+        match instruction {
+            Instruction::MachineCall { nnn } => format!("SYS {}", self.address(nnn)),
+            Instruction::ScrollDown { n } => format!("SCD {}", self.nibble(n)),
+            Instruction::ScrollUp { n } => format!("SCU {}", self.nibble(n)),
+            Instruction::ClearScreen => "CLS".to_string(),
+            Instruction::ReturnFromSubroutine => "RET".to_string(),
+            Instruction::ScrollRight => "SCR".to_string(),
+            Instruction::ScrollLeft => "SCL".to_string(),
+            Instruction::Exit => "EXIT".to_string(),
+            Instruction::LowResolution => "LOW".to_string(),
+            Instruction::HighResolution => "HIGH".to_string(),
+            Instruction::JumpToAddress { nnn } => format!("JP {}", self.address(nnn)),
+            Instruction::CallSubroutine { nnn } => format!("CALL {}", self.address(nnn)),
+            Instruction::SkipNextIfVxEqualsNn { x, nn } => {
+                format!("SE {}, {}", v(x), self.byte(nn))
+            }
+            Instruction::SkipNextIfVxNotEqualsNn { x, nn } => {
+                format!("SNE {}, {}", v(x), self.byte(nn))
+            }
+            Instruction::SkipNextIfVxEqualsVy { x, y } => format!("SE {}, {}", v(x), v(y)),
+            Instruction::StoreVxToVyInMemory { x, y } => format!("SAVE {} - {}", v(x), v(y)),
+            Instruction::LoadVxToVyFromMemory { x, y } => format!("LOAD {} - {}", v(x), v(y)),
+            Instruction::SetVxToNn { x, nn } => format!("LD {}, {}", v(x), self.byte(nn)),
+            Instruction::AddNnToVx { x, nn } => format!("ADD {}, {}", v(x), self.byte(nn)),
+            Instruction::SetVxToVy { x, y } => format!("LD {}, {}", v(x), v(y)),
+            Instruction::SetVxToVxOrVy { x, y } => format!("OR {}, {}", v(x), v(y)),
+            Instruction::SetVxToVxAndVy { x, y } => format!("AND {}, {}", v(x), v(y)),
+            Instruction::SetVxToVxXorVy { x, y } => format!("XOR {}, {}", v(x), v(y)),
+            Instruction::AddVyToVx { x, y } => format!("ADD {}, {}", v(x), v(y)),
+            Instruction::SetVxToVxMinusVy { x, y } => format!("SUB {}, {}", v(x), v(y)),
+            Instruction::ShiftVxRightByOne { x, y } => format!("SHR {}, {}", v(x), v(y)),
+            Instruction::SetVxToVyMinusVx { x, y } => format!("SUBN {}, {}", v(x), v(y)),
+            Instruction::ShiftVxLeftByOne { x, y } => format!("SHL {}, {}", v(x), v(y)),
+            Instruction::SkipNextIfVxNotEqualsVy { x, y } => format!("SNE {}, {}", v(x), v(y)),
+            Instruction::SetIToNnn { nnn } => format!("LD I, {}", self.address(nnn)),
+            Instruction::JumpToAddressPlusV0 { nnn, .. } => format!("JP V0, {}", self.address(nnn)),
+            Instruction::SetVxToRandomNumberAndNn { x, nn } => {
+                format!("RND {}, {}", v(x), self.byte(nn))
+            }
+            Instruction::DrawSprite { x, y, n } => {
+                format!("DRW {}, {}, {}", v(x), v(y), self.nibble(n))
+            }
+            Instruction::SkipNextIfKeyIsPressed { x } => format!("SKP {}", v(x)),
+            Instruction::SkipNextIfKeyIsNotPressed { x } => format!("SKNP {}", v(x)),
+            Instruction::SetIToLongAddress { nnnn } => {
+                format!("LD I, LONG {}", self.long_address(nnnn))
+            }
+            Instruction::SelectPlanes { x } => format!("PLANE {}", self.nibble(x)),
+            Instruction::LoadPattern => "AUDIO".to_string(),
+            Instruction::SetVxToDelayTimer { x } => format!("LD {}, DT", v(x)),
+            Instruction::WaitForKeypress { x } => format!("LD {}, K", v(x)),
+            Instruction::SetDelayTimerToVx { x } => format!("LD DT, {}", v(x)),
+            Instruction::SetSoundTimerToVx { x } => format!("LD ST, {}", v(x)),
+            Instruction::AddVxToI { x } => format!("ADD I, {}", v(x)),
+            Instruction::SetIToSpriteLocation { x } => format!("LD F, {}", v(x)),
+            Instruction::SetIToLargeSpriteLocation { x } => format!("LD HF, {}", v(x)),
+            Instruction::SetBcdOfVxAtI { x } => format!("LD B, {}", v(x)),
+            Instruction::SetPitchToVx { x } => format!("PITCH {}", v(x)),
+            Instruction::StoreRegistersInMemory { x } => format!("LD [I], {}", v(x)),
+            Instruction::LoadRegistersFromMemory { x } => format!("LD {}, [I]", v(x)),
+            Instruction::StoreRegistersInRplFlags { x } => format!("LD R, {}", v(x)),
+            Instruction::LoadRegistersFromRplFlags { x } => format!("LD {}, R", v(x)),
+        }
+    }
+
+    // Octo has no skips, only conditions that skip when they are false, so
+    // every skip is written as the opposite condition
+    fn octo(&self, instruction: Instruction) -> String {
+        let v = |x| self.v(x);
+        match instruction {
+            // Octo has no statement for 0NNN, it is written as its bytes
+            Instruction::MachineCall { nnn } => format!("0x{:02X} 0x{:02X}", nnn >> 8, nnn & 0xFF),
+            Instruction::ScrollDown { n } => format!("scroll-down {}", n),
+            Instruction::ScrollUp { n } => format!("scroll-up {}", n),
+            Instruction::ClearScreen => "clear".to_string(),
+            Instruction::ReturnFromSubroutine => "return".to_string(),
+            Instruction::ScrollRight => "scroll-right".to_string(),
+            Instruction::ScrollLeft => "scroll-left".to_string(),
+            Instruction::Exit => "exit".to_string(),
+            Instruction::LowResolution => "lores".to_string(),
+            Instruction::HighResolution => "hires".to_string(),
+            Instruction::JumpToAddress { nnn } => format!("jump {}", self.address(nnn)),
+            Instruction::CallSubroutine { nnn } => match self.labels.get(&(nnn as usize)) {
+                Some(label) => label.clone(),
+                None => format!(":call 0x{:03X}", nnn),
+            },
+            Instruction::SkipNextIfVxEqualsNn { x, nn } => {
+                format!("if {} != {} then", v(x), self.byte(nn))
+            }
+            Instruction::SkipNextIfVxNotEqualsNn { x, nn } => {
+                format!("if {} == {} then", v(x), self.byte(nn))
+            }
+            Instruction::SkipNextIfVxEqualsVy { x, y } => format!("if {} != {} then", v(x), v(y)),
+            Instruction::StoreVxToVyInMemory { x, y } => format!("save {} - {}", v(x), v(y)),
+            Instruction::LoadVxToVyFromMemory { x, y } => format!("load {} - {}", v(x), v(y)),
+            Instruction::SetVxToNn { x, nn } => format!("{} := {}", v(x), self.byte(nn)),
+            Instruction::AddNnToVx { x, nn } => format!("{} += {}", v(x), self.byte(nn)),
+            Instruction::SetVxToVy { x, y } => format!("{} := {}", v(x), v(y)),
+            Instruction::SetVxToVxOrVy { x, y } => format!("{} |= {}", v(x), v(y)),
+            Instruction::SetVxToVxAndVy { x, y } => format!("{} &= {}", v(x), v(y)),
+            Instruction::SetVxToVxXorVy { x, y } => format!("{} ^= {}", v(x), v(y)),
+            Instruction::AddVyToVx { x, y } => format!("{} += {}", v(x), v(y)),
+            Instruction::SetVxToVxMinusVy { x, y } => format!("{} -= {}", v(x), v(y)),
+            Instruction::ShiftVxRightByOne { x, y } => format!("{} >>= {}", v(x), v(y)),
+            Instruction::SetVxToVyMinusVx { x, y } => format!("{} =- {}", v(x), v(y)),
+            Instruction::ShiftVxLeftByOne { x, y } => format!("{} <<= {}", v(x), v(y)),
+            Instruction::SkipNextIfVxNotEqualsVy { x, y } => {
+                format!("if {} == {} then", v(x), v(y))
+            }
+            Instruction::SetIToNnn { nnn } => format!("i := {}", self.address(nnn)),
+            Instruction::JumpToAddressPlusV0 { nnn, .. } => format!("jump0 {}", self.address(nnn)),
+            Instruction::SetVxToRandomNumberAndNn { x, nn } => {
+                format!("{} := random {}", v(x), self.byte(nn))
+            }
+            Instruction::DrawSprite { x, y, n } => format!("sprite {} {} {}", v(x), v(y), n),
+            Instruction::SkipNextIfKeyIsPressed { x } => format!("if {} -key then", v(x)),
+            Instruction::SkipNextIfKeyIsNotPressed { x } => format!("if {} key then", v(x)),
+            Instruction::SetIToLongAddress { nnnn } => {
+                format!("i := long {}", self.long_address(nnnn))
+            }
+            Instruction::SelectPlanes { x } => format!("plane {}", x),
+            Instruction::LoadPattern => "audio".to_string(),
+            Instruction::SetVxToDelayTimer { x } => format!("{} := delay", v(x)),
+            Instruction::WaitForKeypress { x } => format!("{} := key", v(x)),
+            Instruction::SetDelayTimerToVx { x } => format!("delay := {}", v(x)),
+            Instruction::SetSoundTimerToVx { x } => format!("buzzer := {}", v(x)),
+            Instruction::AddVxToI { x } => format!("i += {}", v(x)),
+            Instruction::SetIToSpriteLocation { x } => format!("i := hex {}", v(x)),
+            Instruction::SetIToLargeSpriteLocation { x } => format!("i := bighex {}", v(x)),
+            Instruction::SetBcdOfVxAtI { x } => format!("bcd {}", v(x)),
+            Instruction::SetPitchToVx { x } => format!("pitch := {}", v(x)),
+            Instruction::StoreRegistersInMemory { x } => format!("save {}", v(x)),
+            Instruction::LoadRegistersFromMemory { x } => format!("load {}", v(x)),
+            Instruction::StoreRegistersInRplFlags { x } => format!("saveflags {}", v(x)),
+            Instruction::LoadRegistersFromRplFlags { x } => format!("loadflags {}", v(x)),
+        }
+    }
+
+    // F000 NNNN addresses all of XO-CHIP memory
+    fn long_address(&self, nnnn: u16) -> String {
+        match (self.labels.get(&(nnnn as usize)), self.syntax) {
+            (Some(label), _) => label.clone(),
+            (None, Syntax::Cowgod) => format!("#{:04X}", nnnn),
+            (None, Syntax::Octo) => format!("0x{:04X}", nnnn),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // LD I, data ; CALL sub ; SE V0, 1 ; LD I, LONG data ; JP back ; RET ; data
+    const ROM: [u8; 19] = [
+        0xA2, 0x0E, 0x22, 0x0C, 0x30, 0x01, 0xF0, 0x00, 0x02, 0x0E, 0x12, 0x06, 0x00, 0xEE, 0xFF,
+        0x81, 0x42, 0x24, 0x18,
+    ];
+
+    #[test]
+    fn traces_code_and_labels_targets() {
+        let text = disassemble(&ROM, InstructionSet::XoChip, Syntax::Cowgod);
+        let expected = "\
+0200  A2 0E                    LD I, data_20E
+0202  22 0C                    CALL sub_20C
+0204  30 01                    SE V0, #01
+label_206:
+0206  F0 00 02 0E              LD I, LONG data_20E
+020A  12 06                    JP label_206
+sub_20C:
+020C  00 EE                    RET
+data_20E:
+020E  FF 81 42 24 18           DB #FF, #81, #42, #24, #18
+";
+        assert_eq!(text, expected);
+    }
+
+    #[test]
+    fn ends_paths_at_unsupported_opcodes() {
+        let code = trace(&ROM, InstructionSet::Chip8);
+        assert!(!code.contains_key(&0x206));
+        // The skip still lands on the second word, a SYS on CHIP-8
+        assert_eq!(
+            code.get(&0x208),
+            Some(&Instruction::MachineCall { nnn: 0x20E })
+        );
+        assert_eq!(code.get(&0x20C), Some(&Instruction::ReturnFromSubroutine));
+    }
+
+    #[test]
+    fn falls_through_later_0nnn_opcodes() {
+        // EXIT ; CLS on CHIP-8, where 00FD is a SYS
+        let rom = [0x00, 0xFD, 0x00, 0xE0];
+        let code = trace(&rom, InstructionSet::Chip8);
+        assert_eq!(
+            code.get(&0x200),
+            Some(&Instruction::MachineCall { nnn: 0x0FD })
+        );
+        assert_eq!(code.get(&0x202), Some(&Instruction::ClearScreen));
+        assert!(!trace(&rom, InstructionSet::SuperChip10).contains_key(&0x202));
+    }
+
+    #[test]
+    fn falls_through_machine_calls() {
+        let rom = [0x01, 0x23, 0x00, 0xE0, 0x12, 0x02];
+        let code = trace(&rom, InstructionSet::Chip8);
+        assert_eq!(
+            code.get(&0x200),
+            Some(&Instruction::MachineCall { nnn: 0x123 })
+        );
+        assert_eq!(code.get(&0x202), Some(&Instruction::ClearScreen));
+        assert_eq!(
+            code.get(&0x204),
+            Some(&Instruction::JumpToAddress { nnn: 0x202 })
+        );
+    }
+
+    #[test]
+    fn skips_over_both_words_of_long_address() {
+        // SE V0, 0 ; LD I, LONG 0 ; EXIT
+        let rom = [0x30, 0x00, 0xF0, 0x00, 0x00, 0x00, 0x00, 0xFD];
+        let code = trace(&rom, InstructionSet::XoChip);
+        assert_eq!(code.get(&0x206), Some(&Instruction::Exit));
+        assert!(!code.contains_key(&0x204));
+    }
+
+    #[test]
+    fn writes_octo_syntax() {
+        let text = disassemble(&ROM, InstructionSet::XoChip, Syntax::Octo);
+        assert!(text.contains(": sub_20C\n"));
+        assert!(text.contains("i := long data_20E"));
+        assert!(text.contains("jump label_206"));
+        assert!(text.contains("return"));
+    }
+}
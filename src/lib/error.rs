@@ -0,0 +1,172 @@
+use crate::cpu::MAX_STACK_DEPTH;
+use std::error::Error;
+use std::fmt;
+
+// Errors that stop the machine, carrying the address and opcode of the
+// instruction that caused them
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Chip8Error {
+    UnknownOpcode {
+        pc: u16,
+        opcode: u16,
+    },
+    StackOverflow {
+        pc: u16,
+        opcode: u16,
+    },
+    StackUnderflow {
+        pc: u16,
+        opcode: u16,
+    },
+    MemoryFault {
+        pc: u16,
+        opcode: u16,
+        address: usize,
+    },
+    RomTooLarge {
+        size: usize,
+        max: usize,
+    },
+    InvalidFontAddress {
+        address: u16,
+    },
+    InvalidStackDepth {
+        depth: u8,
+    },
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Chip8Error::UnknownOpcode { pc, opcode } => {
+                write!(f, "Unknown opcode {:04X} at {:#05X}", opcode, pc)
+            }
+            Chip8Error::StackOverflow { pc, opcode } => {
+                write!(f, "Stack overflow by {:04X} at {:#05X}", opcode, pc)
+            }
+            Chip8Error::StackUnderflow { pc, opcode } => {
+                write!(f, "Stack underflow by {:04X} at {:#05X}", opcode, pc)
+            }
+            Chip8Error::MemoryFault {
+                pc,
+                opcode,
+                address,
+            } => write!(
+                f,
+                "Memory fault accessing {:#05X} by {:04X} at {:#05X}",
+                address, opcode, pc
+            ),
+            Chip8Error::RomTooLarge { size, max } => {
+                write!(f, "ROM is too large! size: {}, max: {}", size, max)
+            }
+            Chip8Error::InvalidFontAddress { address } => write!(
+                f,
+                "Font address {:#05X} leaves no room for the fonts below 0x200",
+                address
+            ),
+            Chip8Error::InvalidStackDepth { depth } => {
+                write!(
+                    f,
+                    "Stack depth {} is not between 1 and {}",
+                    depth, MAX_STACK_DEPTH
+                )
+            }
+        }
+    }
+}
+
+impl Error for Chip8Error {}
+
+// An opcode that is not an instruction of any instruction set
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecodeError {
+    pub opcode: u16,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Unknown opcode {:04X}", self.opcode)
+    }
+}
+
+impl Error for DecodeError {}
+
+// Reasons a save state cannot be restored
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StateError {
+    InvalidFormat,
+    UnsupportedVersion(u8),
+    RomMismatch,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StateError::InvalidFormat => write!(f, "Not a valid save state"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported save state version {}", version)
+            }
+            StateError::RomMismatch => write!(f, "Save state was made for a different ROM"),
+        }
+    }
+}
+
+impl Error for StateError {}
+
+// Reasons a movie cannot be read or replayed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MovieError {
+    InvalidFormat,
+    UnsupportedVersion(u8),
+    RomMismatch,
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MovieError::InvalidFormat => write!(f, "Not a valid movie"),
+            MovieError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported movie version {}", version)
+            }
+            MovieError::RomMismatch => write!(f, "Movie was recorded with a different ROM"),
+        }
+    }
+}
+
+impl Error for MovieError {}
+
+// Movies share the encoding of the options with save states
+impl From<StateError> for MovieError {
+    fn from(error: StateError) -> MovieError {
+        match error {
+            StateError::InvalidFormat => MovieError::InvalidFormat,
+            StateError::UnsupportedVersion(version) => MovieError::UnsupportedVersion(version),
+            StateError::RomMismatch => MovieError::RomMismatch,
+        }
+    }
+}
+
+// What went wrong inside an instruction, before the CPU adds the address and
+// opcode it happened at
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Fault {
+    UnknownOpcode,
+    StackOverflow,
+    StackUnderflow,
+    OutOfBounds(usize),
+}
+
+impl Fault {
+    pub(crate) fn at(self, pc: u16, opcode: u16) -> Chip8Error {
+        match self {
+            Fault::UnknownOpcode => Chip8Error::UnknownOpcode { pc, opcode },
+            Fault::StackOverflow => Chip8Error::StackOverflow { pc, opcode },
+            Fault::StackUnderflow => Chip8Error::StackUnderflow { pc, opcode },
+            Fault::OutOfBounds(address) => Chip8Error::MemoryFault {
+                pc,
+                opcode,
+                address,
+            },
+        }
+    }
+}
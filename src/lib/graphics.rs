@@ -1,65 +1,179 @@
-use sdl2::pixels::Color;
-use sdl2::rect::Rect;
-use sdl2::render::Canvas;
-use sdl2::video::Window;
-use sdl2::Sdl;
-
-pub const WIDTH: usize = 64;
-pub const HEIGHT: usize = 32;
-pub const SCALE: u32 = 10;
-pub const FOREGROUND_COLOR: Color = Color::RGB(255, 255, 255);
-pub const BACKGROUND_COLOR: Color = Color::RGB(0, 0, 0);
-
-pub struct Display {
-    canvas: Canvas<Window>,
-    background_color: Color,
-    foreground_color: Color,
+pub const LORES_WIDTH: usize = 64;
+pub const LORES_HEIGHT: usize = 32;
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+
+// Bit masks of the XO-CHIP bitplanes, plain CHIP-8 only draws on the first
+pub const FIRST_PLANE: u8 = 0b01;
+pub const SECOND_PLANE: u8 = 0b10;
+pub const ALL_PLANES: u8 = FIRST_PLANE | SECOND_PLANE;
+
+// The display, 64 x 32 in low resolution or 128 x 64 in high resolution.
+// Every pixel holds one bit per bitplane, which makes it one of four colors.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Framebuffer {
+    hires: bool,
+    pixels: [[u8; HIRES_WIDTH]; HIRES_HEIGHT], // Indexed as [y][x]
 }
 
-impl Display {
-    pub fn new(sdl_context: Sdl) -> Display {
-        // Create an SDL2 window with canvas
-        let video_subsystem = sdl_context.video().unwrap();
-        let window = video_subsystem
-            .window(
-                "Rust CHIP-8 interpreter",
-                WIDTH as u32 * SCALE,
-                HEIGHT as u32 * SCALE,
-            )
-            .position_centered()
-            .build()
-            .unwrap();
-        let canvas = window.into_canvas().build().unwrap();
-
-        Display {
-            canvas,
-            background_color: BACKGROUND_COLOR,
-            foreground_color: FOREGROUND_COLOR,
+impl Framebuffer {
+    pub fn new() -> Framebuffer {
+        Framebuffer {
+            hires: false,
+            pixels: [[0; HIRES_WIDTH]; HIRES_HEIGHT],
+        }
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    pub fn width(&self) -> usize {
+        if self.hires {
+            HIRES_WIDTH
+        } else {
+            LORES_WIDTH
         }
     }
 
+    pub fn height(&self) -> usize {
+        if self.hires {
+            HIRES_HEIGHT
+        } else {
+            LORES_HEIGHT
+        }
+    }
+
+    // Color of a pixel, 0 for background through 3 for both planes set
+    pub fn get(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y][x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, color: u8) {
+        self.pixels[y][x] = color & ALL_PLANES;
+    }
+
+    // Flips a pixel on the given plane, returns whether it was turned off
+    pub fn flip(&mut self, x: usize, y: usize, plane: u8) -> bool {
+        self.pixels[y][x] ^= plane;
+        self.pixels[y][x] & plane == 0
+    }
+
+    // Rows of the current resolution, each as wide as the current resolution
+    pub fn rows(&self) -> impl Iterator<Item = &[u8]> {
+        let width = self.width();
+        self.pixels[..self.height()]
+            .iter()
+            .map(move |row| &row[..width])
+    }
+
     pub fn clear(&mut self) {
-        self.canvas.set_draw_color(self.background_color);
-        self.canvas.clear();
+        self.pixels = [[0; HIRES_WIDTH]; HIRES_HEIGHT];
+    }
+
+    pub fn clear_planes(&mut self, planes: u8) {
+        for pixel in self.pixels.iter_mut().flatten() {
+            *pixel &= !planes;
+        }
     }
 
-    pub fn draw(&mut self, pixels: &[[bool; WIDTH]; HEIGHT]) {
-        // Clear the canvas
+    // Switching resolution clears the display
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
         self.clear();
+    }
+
+    pub fn scroll_down(&mut self, n: usize, planes: u8) {
+        let height = self.height();
+        for y in (0..height).rev() {
+            let source = if y >= n { Some(y - n) } else { None };
+            self.move_row(source, y, planes);
+        }
+    }
+
+    pub fn scroll_up(&mut self, n: usize, planes: u8) {
+        let height = self.height();
+        for y in 0..height {
+            let source = if y + n < height { Some(y + n) } else { None };
+            self.move_row(source, y, planes);
+        }
+    }
 
-        // Draw pixels to the display
-        self.canvas.set_draw_color(self.foreground_color);
-        for y in 0..HEIGHT {
-            for x in 0..WIDTH {
-                if pixels[y][x] {
-                    let x = ((x as u32) * SCALE) as i32;
-                    let y = ((y as u32) * SCALE) as i32;
-                    self.canvas
-                        .fill_rect(Rect::new(x, y, SCALE, SCALE))
-                        .expect("Failed to draw pixel");
-                }
+    pub fn scroll_right(&mut self, n: usize, planes: u8) {
+        let width = self.width();
+        for row in self.pixels.iter_mut() {
+            for x in (0..width).rev() {
+                let source = if x >= n { row[x - n] } else { 0 };
+                row[x] = (row[x] & !planes) | (source & planes);
             }
         }
-        self.canvas.present();
+    }
+
+    pub fn scroll_left(&mut self, n: usize, planes: u8) {
+        let width = self.width();
+        for row in self.pixels.iter_mut() {
+            for x in 0..width {
+                let source = if x + n < width { row[x + n] } else { 0 };
+                row[x] = (row[x] & !planes) | (source & planes);
+            }
+        }
+    }
+
+    // Copies the given planes of row `source` into row `target`, or clears
+    // them when there is no source row
+    fn move_row(&mut self, source: Option<usize>, target: usize, planes: u8) {
+        for x in 0..HIRES_WIDTH {
+            let from = source.map_or(0, |source| self.pixels[source][x]);
+            let to = &mut self.pixels[target][x];
+            *to = (*to & !planes) | (from & planes);
+        }
+    }
+}
+
+impl Default for Framebuffer {
+    fn default() -> Framebuffer {
+        Framebuffer::new()
+    }
+}
+
+// A video sink that the CPU presents its framebuffer to
+pub trait Video {
+    // Blanks the output, by default by presenting an empty framebuffer
+    fn clear(&mut self) {
+        self.draw(&Framebuffer::new());
+    }
+
+    fn draw(&mut self, framebuffer: &Framebuffer);
+}
+
+// Display that keeps the last presented frame in memory, for headless use
+pub struct NullDisplay {
+    pub framebuffer: Framebuffer,
+    pub frames_drawn: u64,
+}
+
+impl NullDisplay {
+    pub fn new() -> NullDisplay {
+        NullDisplay {
+            framebuffer: Framebuffer::new(),
+            frames_drawn: 0,
+        }
+    }
+}
+
+impl Default for NullDisplay {
+    fn default() -> NullDisplay {
+        NullDisplay::new()
+    }
+}
+
+impl Video for NullDisplay {
+    fn clear(&mut self) {
+        self.framebuffer.clear();
+    }
+
+    fn draw(&mut self, framebuffer: &Framebuffer) {
+        self.framebuffer.clone_from(framebuffer);
+        self.frames_drawn += 1;
     }
 }
@@ -0,0 +1,258 @@
+use crate::graphics::Framebuffer;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+// Gray levels of the background, the first plane, the second plane and both
+pub const GRAYSCALE: [u8; 4] = [0, 255, 170, 85];
+
+// Characters of the background, the first plane, the second plane and both
+pub const ASCII: [char; 4] = ['.', '#', '+', '@'];
+
+// File formats a framebuffer can be written as
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,   // 8-bit grayscale
+    Pbm,   // Plain 1-bit bitmap, any lit pixel is 1
+    Ascii, // One character per pixel, one line per row
+}
+
+impl ImageFormat {
+    // The format a file name asks for, by its extension
+    pub fn from_path(path: &Path) -> Option<ImageFormat> {
+        path.extension()?.to_str()?.parse().ok()
+    }
+
+    // Encodes `framebuffer` in the current resolution
+    pub fn encode(self, framebuffer: &Framebuffer) -> io::Result<Vec<u8>> {
+        match self {
+            ImageFormat::Png => encode_png(framebuffer),
+            ImageFormat::Pbm => Ok(Bitmap::from_framebuffer(framebuffer).to_pbm()),
+            ImageFormat::Ascii => Ok(encode_ascii(framebuffer).into_bytes()),
+        }
+    }
+}
+
+impl FromStr for ImageFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ImageFormat, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "png" => Ok(ImageFormat::Png),
+            "pbm" => Ok(ImageFormat::Pbm),
+            "ascii" | "txt" => Ok(ImageFormat::Ascii),
+            _ => Err(format!("Unknown image format: {}", s)),
+        }
+    }
+}
+
+fn encode_png(framebuffer: &Framebuffer) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let width = framebuffer.width() as u32;
+    let height = framebuffer.height() as u32;
+    let mut encoder = png::Encoder::new(&mut bytes, width, height);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let data: Vec<u8> = framebuffer
+        .rows()
+        .flatten()
+        .map(|&color| GRAYSCALE[color as usize])
+        .collect();
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&data))
+        .map_err(io::Error::other)?;
+    Ok(bytes)
+}
+
+pub fn encode_ascii(framebuffer: &Framebuffer) -> String {
+    let mut text = String::new();
+    for row in framebuffer.rows() {
+        text.extend(row.iter().map(|&color| ASCII[color as usize]));
+        text.push('\n');
+    }
+    text
+}
+
+// A 1-bit image, what golden images of the regression runner are kept as
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Bitmap {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<bool>, // Row by row, true for a lit pixel
+}
+
+impl Bitmap {
+    pub fn from_framebuffer(framebuffer: &Framebuffer) -> Bitmap {
+        Bitmap {
+            width: framebuffer.width(),
+            height: framebuffer.height(),
+            pixels: framebuffer
+                .rows()
+                .flatten()
+                .map(|&color| color != 0)
+                .collect(),
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        x < self.width && y < self.height && self.pixels[y * self.width + x]
+    }
+
+    // Reads a plain (P1) PBM file
+    pub fn from_pbm(bytes: &[u8]) -> Result<Bitmap, String> {
+        let text = String::from_utf8_lossy(bytes);
+        let mut tokens = text
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or(""))
+            .flat_map(|line| line.split_whitespace());
+        if tokens.next() != Some("P1") {
+            return Err("Not a plain PBM image".to_string());
+        }
+        let mut size = || -> Result<usize, String> {
+            tokens
+                .next()
+                .and_then(|token| token.parse().ok())
+                .ok_or_else(|| "Invalid PBM size".to_string())
+        };
+        let (width, height) = (size()?, size()?);
+        let area = width
+            .checked_mul(height)
+            .ok_or_else(|| "Invalid PBM size".to_string())?;
+
+        // Pixels do not need whitespace between them
+        let pixels = tokens
+            .flat_map(|token| token.chars())
+            .take(area)
+            .map(|c| match c {
+                '0' => Ok(false),
+                '1' => Ok(true),
+                _ => Err(format!("Invalid PBM pixel: {}", c)),
+            })
+            .collect::<Result<Vec<bool>, String>>()?;
+        if pixels.len() != area {
+            return Err("PBM image is truncated".to_string());
+        }
+        Ok(Bitmap {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    // Writes a plain (P1) PBM file
+    pub fn to_pbm(&self) -> Vec<u8> {
+        let mut text = format!("P1\n{} {}\n", self.width, self.height);
+        for row in self.pixels.chunks(self.width.max(1)) {
+            let bits: Vec<&str> = row.iter().map(|&lit| if lit { "1" } else { "0" }).collect();
+            text.push_str(&bits.join(" "));
+            text.push('\n');
+        }
+        text.into_bytes()
+    }
+
+    // Number of pixels that differ, None when the images differ in size
+    pub fn count_differences(&self, other: &Bitmap) -> Option<usize> {
+        if (self.width, self.height) != (other.width, other.height) {
+            return None;
+        }
+        let differences = self
+            .pixels
+            .iter()
+            .zip(&other.pixels)
+            .filter(|(a, b)| a != b)
+            .count();
+        Some(differences)
+    }
+
+    // A plain (P3) PPM image of the differences with `actual`: lit in both
+    // is white, only expected is red and only actual is green
+    pub fn diff_ppm(&self, actual: &Bitmap) -> Vec<u8> {
+        let width = self.width.max(actual.width);
+        let height = self.height.max(actual.height);
+        let mut text = format!("P3\n{} {}\n255\n", width, height);
+        for y in 0..height {
+            let row: Vec<&str> = (0..width)
+                .map(|x| match (self.get(x, y), actual.get(x, y)) {
+                    (true, true) => "255 255 255",
+                    (true, false) => "255 0 0",
+                    (false, true) => "0 255 0",
+                    (false, false) => "0 0 0",
+                })
+                .collect();
+            text.push_str(&row.join("  "));
+            text.push('\n');
+        }
+        text.into_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bitmap(width: usize, height: usize, lit: &[(usize, usize)]) -> Bitmap {
+        let mut pixels = vec![false; width * height];
+        for &(x, y) in lit {
+            pixels[y * width + x] = true;
+        }
+        Bitmap {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    #[test]
+    fn round_trips_pbm() {
+        let image = bitmap(5, 3, &[(0, 0), (4, 1), (2, 2)]);
+        let read = Bitmap::from_pbm(&image.to_pbm()).unwrap();
+        assert_eq!((read.width, read.height), (5, 3));
+        assert_eq!(read.pixels, image.pixels);
+    }
+
+    #[test]
+    fn round_trips_framebuffer() {
+        let mut framebuffer = Framebuffer::new();
+        framebuffer.set(3, 7, 1);
+        framebuffer.set(63, 31, 2);
+        let image = Bitmap::from_framebuffer(&framebuffer);
+        let read = Bitmap::from_pbm(&image.to_pbm()).unwrap();
+        assert_eq!((read.width, read.height), (64, 32));
+        assert!(read.get(3, 7) && read.get(63, 31) && !read.get(0, 0));
+    }
+
+    #[test]
+    fn reads_comments_and_packed_pixels() {
+        let read = Bitmap::from_pbm(b"P1\n# a comment\n3 2 # size\n101\n0 1 0\n").unwrap();
+        assert_eq!(read.pixels, [true, false, true, false, true, false]);
+    }
+
+    #[test]
+    fn rejects_invalid_pbm() {
+        assert!(Bitmap::from_pbm(b"P4\n1 1\n1\n").is_err());
+        assert!(Bitmap::from_pbm(b"P1\nx 1\n1\n").is_err());
+        assert!(Bitmap::from_pbm(b"P1\n2 2\n1 0 1\n").is_err());
+        assert!(Bitmap::from_pbm(b"P1\n2 1\n1 2\n").is_err());
+        assert!(Bitmap::from_pbm(b"P1\n2 1\n1 x\n").is_err());
+    }
+
+    #[test]
+    fn rejects_overflowing_pbm_size() {
+        let huge = format!("P1\n{} {}\n1\n", usize::MAX, 2);
+        assert_eq!(
+            Bitmap::from_pbm(huge.as_bytes()),
+            Err("Invalid PBM size".to_string())
+        );
+    }
+
+    #[test]
+    fn counts_differences_of_same_size_only() {
+        let expected = bitmap(4, 2, &[(0, 0), (1, 1)]);
+        let actual = bitmap(4, 2, &[(0, 0), (2, 1), (3, 1)]);
+        assert_eq!(expected.count_differences(&actual), Some(3));
+        assert_eq!(expected.count_differences(&expected), Some(0));
+        assert_eq!(expected.count_differences(&bitmap(2, 4, &[])), None);
+    }
+}
@@ -0,0 +1,409 @@
+use crate::error::DecodeError;
+use crate::platform::InstructionSet;
+
+/*
+| An instruction decoded from its opcode, named after the function in `ops`
+| that executes it. The operands are the fields of the opcode:
+|
+| nnn = 0000NNNN NNNNNNNN | low byte + lower 4 bits of high byte
+|  nn = 00000000 NNNNNNNN | low byte
+|   n = 00000000 0000NNNN | lower 4 bits of low byte
+|   x = 0000XXXX 00000000 | lower 4 bits of high byte
+|   y = 00000000 YYYY0000 | upper 4 bits of low byte
+|
+| F000 NNNN is the only instruction of two words, nnnn is the second one.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Instruction {
+    /// 0NNN, a machine code routine on the COSMAC VIP, ignored
+    MachineCall { nnn: u16 },
+    /// 00CN [SUPER-CHIP 1.1]
+    ScrollDown { n: u8 },
+    /// 00DN [XO-CHIP]
+    ScrollUp { n: u8 },
+    /// 00E0
+    ClearScreen,
+    /// 00EE
+    ReturnFromSubroutine,
+    /// 00FB [SUPER-CHIP 1.1]
+    ScrollRight,
+    /// 00FC [SUPER-CHIP 1.1]
+    ScrollLeft,
+    /// 00FD [SUPER-CHIP]
+    Exit,
+    /// 00FE [SUPER-CHIP]
+    LowResolution,
+    /// 00FF [SUPER-CHIP]
+    HighResolution,
+    /// 1NNN
+    JumpToAddress { nnn: u16 },
+    /// 2NNN
+    CallSubroutine { nnn: u16 },
+    /// 3XNN
+    SkipNextIfVxEqualsNn { x: u8, nn: u8 },
+    /// 4XNN
+    SkipNextIfVxNotEqualsNn { x: u8, nn: u8 },
+    /// 5XY0
+    SkipNextIfVxEqualsVy { x: u8, y: u8 },
+    /// 5XY2 [XO-CHIP]
+    StoreVxToVyInMemory { x: u8, y: u8 },
+    /// 5XY3 [XO-CHIP]
+    LoadVxToVyFromMemory { x: u8, y: u8 },
+    /// 6XNN
+    SetVxToNn { x: u8, nn: u8 },
+    /// 7XNN
+    AddNnToVx { x: u8, nn: u8 },
+    /// 8XY0
+    SetVxToVy { x: u8, y: u8 },
+    /// 8XY1
+    SetVxToVxOrVy { x: u8, y: u8 },
+    /// 8XY2
+    SetVxToVxAndVy { x: u8, y: u8 },
+    /// 8XY3
+    SetVxToVxXorVy { x: u8, y: u8 },
+    /// 8XY4
+    AddVyToVx { x: u8, y: u8 },
+    /// 8XY5
+    SetVxToVxMinusVy { x: u8, y: u8 },
+    /// 8XY6
+    ShiftVxRightByOne { x: u8, y: u8 },
+    /// 8XY7
+    SetVxToVyMinusVx { x: u8, y: u8 },
+    /// 8XYE
+    ShiftVxLeftByOne { x: u8, y: u8 },
+    /// 9XY0
+    SkipNextIfVxNotEqualsVy { x: u8, y: u8 },
+    /// ANNN
+    SetIToNnn { nnn: u16 },
+    /// BNNN
+    JumpToAddressPlusV0 { x: u8, nnn: u16 },
+    /// CXNN
+    SetVxToRandomNumberAndNn { x: u8, nn: u8 },
+    /// DXYN
+    DrawSprite { x: u8, y: u8, n: u8 },
+    /// EX9E
+    SkipNextIfKeyIsPressed { x: u8 },
+    /// EXA1
+    SkipNextIfKeyIsNotPressed { x: u8 },
+    /// F000 NNNN [XO-CHIP]
+    SetIToLongAddress { nnnn: u16 },
+    /// FN01 [XO-CHIP]
+    SelectPlanes { x: u8 },
+    /// F002 [XO-CHIP]
+    LoadPattern,
+    /// FX07
+    SetVxToDelayTimer { x: u8 },
+    /// FX0A
+    WaitForKeypress { x: u8 },
+    /// FX15
+    SetDelayTimerToVx { x: u8 },
+    /// FX18
+    SetSoundTimerToVx { x: u8 },
+    /// FX1E
+    AddVxToI { x: u8 },
+    /// FX29
+    SetIToSpriteLocation { x: u8 },
+    /// FX30 [SUPER-CHIP 1.1]
+    SetIToLargeSpriteLocation { x: u8 },
+    /// FX33
+    SetBcdOfVxAtI { x: u8 },
+    /// FX3A [XO-CHIP]
+    SetPitchToVx { x: u8 },
+    /// FX55
+    StoreRegistersInMemory { x: u8 },
+    /// FX65
+    LoadRegistersFromMemory { x: u8 },
+    /// FX75 [SUPER-CHIP]
+    StoreRegistersInRplFlags { x: u8 },
+    /// FX85 [SUPER-CHIP]
+    LoadRegistersFromRplFlags { x: u8 },
+}
+
+impl Instruction {
+    // The first instruction set that has this instruction
+    pub fn instruction_set(self) -> InstructionSet {
+        match self {
+            Instruction::Exit
+            | Instruction::LowResolution
+            | Instruction::HighResolution
+            | Instruction::StoreRegistersInRplFlags { .. }
+            | Instruction::LoadRegistersFromRplFlags { .. } => InstructionSet::SuperChip10,
+            Instruction::ScrollDown { .. }
+            | Instruction::ScrollRight
+            | Instruction::ScrollLeft
+            | Instruction::SetIToLargeSpriteLocation { .. } => InstructionSet::SuperChip,
+            Instruction::ScrollUp { .. }
+            | Instruction::StoreVxToVyInMemory { .. }
+            | Instruction::LoadVxToVyFromMemory { .. }
+            | Instruction::SetIToLongAddress { .. }
+            | Instruction::SelectPlanes { .. }
+            | Instruction::LoadPattern
+            | Instruction::SetPitchToVx { .. } => InstructionSet::XoChip,
+            _ => InstructionSet::Chip8,
+        }
+    }
+
+    // Bytes the instruction takes in memory, F000 is followed by its address
+    pub fn size(self) -> u16 {
+        match self {
+            Instruction::SetIToLongAddress { .. } => 4,
+            _ => 2,
+        }
+    }
+}
+
+// Decodes an opcode of any instruction set, see `Instruction::instruction_set`
+// for whether a machine supports the result. `next` is the word after the
+// opcode, which F000 NNNN fails without
+pub fn decode(opcode: u16, next: Option<u16>) -> Result<Instruction, DecodeError> {
+    let nnn = opcode & 0x0FFF;
+    let nn = (opcode & 0x00FF) as u8;
+    let n = (opcode & 0x000F) as u8;
+    let x = ((opcode & 0x0F00) >> 8) as u8;
+    let y = ((opcode & 0x00F0) >> 4) as u8;
+
+    let instruction = match opcode >> 12 {
+        0x0 => match nnn {
+            0x0C0..=0x0CF => Instruction::ScrollDown { n },
+            0x0D0..=0x0DF => Instruction::ScrollUp { n },
+            0x0E0 => Instruction::ClearScreen,
+            0x0EE => Instruction::ReturnFromSubroutine,
+            0x0FB => Instruction::ScrollRight,
+            0x0FC => Instruction::ScrollLeft,
+            0x0FD => Instruction::Exit,
+            0x0FE => Instruction::LowResolution,
+            0x0FF => Instruction::HighResolution,
+            _ => Instruction::MachineCall { nnn },
+        },
+        0x1 => Instruction::JumpToAddress { nnn },
+        0x2 => Instruction::CallSubroutine { nnn },
+        0x3 => Instruction::SkipNextIfVxEqualsNn { x, nn },
+        0x4 => Instruction::SkipNextIfVxNotEqualsNn { x, nn },
+        0x5 => match n {
+            0x0 => Instruction::SkipNextIfVxEqualsVy { x, y },
+            0x2 => Instruction::StoreVxToVyInMemory { x, y },
+            0x3 => Instruction::LoadVxToVyFromMemory { x, y },
+            _ => return Err(DecodeError { opcode }),
+        },
+        0x6 => Instruction::SetVxToNn { x, nn },
+        0x7 => Instruction::AddNnToVx { x, nn },
+        0x8 => match n {
+            0x0 => Instruction::SetVxToVy { x, y },
+            0x1 => Instruction::SetVxToVxOrVy { x, y },
+            0x2 => Instruction::SetVxToVxAndVy { x, y },
+            0x3 => Instruction::SetVxToVxXorVy { x, y },
+            0x4 => Instruction::AddVyToVx { x, y },
+            0x5 => Instruction::SetVxToVxMinusVy { x, y },
+            0x6 => Instruction::ShiftVxRightByOne { x, y },
+            0x7 => Instruction::SetVxToVyMinusVx { x, y },
+            0xE => Instruction::ShiftVxLeftByOne { x, y },
+            _ => return Err(DecodeError { opcode }),
+        },
+        0x9 => match n {
+            0x0 => Instruction::SkipNextIfVxNotEqualsVy { x, y },
+            _ => return Err(DecodeError { opcode }),
+        },
+        0xA => Instruction::SetIToNnn { nnn },
+        0xB => Instruction::JumpToAddressPlusV0 { x, nnn },
+        0xC => Instruction::SetVxToRandomNumberAndNn { x, nn },
+        0xD => Instruction::DrawSprite { x, y, n },
+        0xE => match nn {
+            0x9E => Instruction::SkipNextIfKeyIsPressed { x },
+            0xA1 => Instruction::SkipNextIfKeyIsNotPressed { x },
+            _ => return Err(DecodeError { opcode }),
+        },
+        0xF => match nn {
+            0x00 if x == 0 => Instruction::SetIToLongAddress {
+                nnnn: next.ok_or(DecodeError { opcode })?,
+            },
+            0x01 => Instruction::SelectPlanes { x },
+            0x02 if x == 0 => Instruction::LoadPattern,
+            0x07 => Instruction::SetVxToDelayTimer { x },
+            0x0A => Instruction::WaitForKeypress { x },
+            0x15 => Instruction::SetDelayTimerToVx { x },
+            0x18 => Instruction::SetSoundTimerToVx { x },
+            0x1E => Instruction::AddVxToI { x },
+            0x29 => Instruction::SetIToSpriteLocation { x },
+            0x30 => Instruction::SetIToLargeSpriteLocation { x },
+            0x33 => Instruction::SetBcdOfVxAtI { x },
+            0x3A => Instruction::SetPitchToVx { x },
+            0x55 => Instruction::StoreRegistersInMemory { x },
+            0x65 => Instruction::LoadRegistersFromMemory { x },
+            0x75 => Instruction::StoreRegistersInRplFlags { x },
+            0x85 => Instruction::LoadRegistersFromRplFlags { x },
+            _ => return Err(DecodeError { opcode }),
+        },
+        _ => return Err(DecodeError { opcode }),
+    };
+    Ok(instruction)
+}
+
+// Decodes an opcode as a machine with `instruction_set` runs it. Opcodes of
+// later instruction sets are unknown, except in the 0NNN range where the
+// machine sees a machine code routine like any other 0NNN.
+pub fn decode_for(
+    opcode: u16,
+    next: Option<u16>,
+    instruction_set: InstructionSet,
+) -> Result<Instruction, DecodeError> {
+    match decode(opcode, next)? {
+        instruction if instruction.instruction_set() <= instruction_set => Ok(instruction),
+        _ if opcode >> 12 == 0x0 => Ok(Instruction::MachineCall {
+            nnn: opcode & 0x0FFF,
+        }),
+        _ => Err(DecodeError { opcode }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_every_group() {
+        let cases = [
+            (0x0123, Instruction::MachineCall { nnn: 0x123 }),
+            (0x00C5, Instruction::ScrollDown { n: 5 }),
+            (0x00DA, Instruction::ScrollUp { n: 0xA }),
+            (0x00E0, Instruction::ClearScreen),
+            (0x00EE, Instruction::ReturnFromSubroutine),
+            (0x00FB, Instruction::ScrollRight),
+            (0x00FC, Instruction::ScrollLeft),
+            (0x00FD, Instruction::Exit),
+            (0x00FE, Instruction::LowResolution),
+            (0x00FF, Instruction::HighResolution),
+            (0x1ABC, Instruction::JumpToAddress { nnn: 0xABC }),
+            (0x2ABC, Instruction::CallSubroutine { nnn: 0xABC }),
+            (
+                0x3A12,
+                Instruction::SkipNextIfVxEqualsNn { x: 0xA, nn: 0x12 },
+            ),
+            (
+                0x4A12,
+                Instruction::SkipNextIfVxNotEqualsNn { x: 0xA, nn: 0x12 },
+            ),
+            (0x5AB0, Instruction::SkipNextIfVxEqualsVy { x: 0xA, y: 0xB }),
+            (0x5AB2, Instruction::StoreVxToVyInMemory { x: 0xA, y: 0xB }),
+            (0x5AB3, Instruction::LoadVxToVyFromMemory { x: 0xA, y: 0xB }),
+            (0x6A12, Instruction::SetVxToNn { x: 0xA, nn: 0x12 }),
+            (0x7A12, Instruction::AddNnToVx { x: 0xA, nn: 0x12 }),
+            (0x8AB0, Instruction::SetVxToVy { x: 0xA, y: 0xB }),
+            (0x8AB1, Instruction::SetVxToVxOrVy { x: 0xA, y: 0xB }),
+            (0x8AB2, Instruction::SetVxToVxAndVy { x: 0xA, y: 0xB }),
+            (0x8AB3, Instruction::SetVxToVxXorVy { x: 0xA, y: 0xB }),
+            (0x8AB4, Instruction::AddVyToVx { x: 0xA, y: 0xB }),
+            (0x8AB5, Instruction::SetVxToVxMinusVy { x: 0xA, y: 0xB }),
+            (0x8AB6, Instruction::ShiftVxRightByOne { x: 0xA, y: 0xB }),
+            (0x8AB7, Instruction::SetVxToVyMinusVx { x: 0xA, y: 0xB }),
+            (0x8ABE, Instruction::ShiftVxLeftByOne { x: 0xA, y: 0xB }),
+            (
+                0x9AB0,
+                Instruction::SkipNextIfVxNotEqualsVy { x: 0xA, y: 0xB },
+            ),
+            (0xAABC, Instruction::SetIToNnn { nnn: 0xABC }),
+            (
+                0xBABC,
+                Instruction::JumpToAddressPlusV0 { x: 0xA, nnn: 0xABC },
+            ),
+            (
+                0xCA12,
+                Instruction::SetVxToRandomNumberAndNn { x: 0xA, nn: 0x12 },
+            ),
+            (
+                0xDAB5,
+                Instruction::DrawSprite {
+                    x: 0xA,
+                    y: 0xB,
+                    n: 5,
+                },
+            ),
+            (0xEA9E, Instruction::SkipNextIfKeyIsPressed { x: 0xA }),
+            (0xEAA1, Instruction::SkipNextIfKeyIsNotPressed { x: 0xA }),
+            (0xFA01, Instruction::SelectPlanes { x: 0xA }),
+            (0xF002, Instruction::LoadPattern),
+            (0xFA07, Instruction::SetVxToDelayTimer { x: 0xA }),
+            (0xFA0A, Instruction::WaitForKeypress { x: 0xA }),
+            (0xFA15, Instruction::SetDelayTimerToVx { x: 0xA }),
+            (0xFA18, Instruction::SetSoundTimerToVx { x: 0xA }),
+            (0xFA1E, Instruction::AddVxToI { x: 0xA }),
+            (0xFA29, Instruction::SetIToSpriteLocation { x: 0xA }),
+            (0xFA30, Instruction::SetIToLargeSpriteLocation { x: 0xA }),
+            (0xFA33, Instruction::SetBcdOfVxAtI { x: 0xA }),
+            (0xFA3A, Instruction::SetPitchToVx { x: 0xA }),
+            (0xFA55, Instruction::StoreRegistersInMemory { x: 0xA }),
+            (0xFA65, Instruction::LoadRegistersFromMemory { x: 0xA }),
+            (0xF775, Instruction::StoreRegistersInRplFlags { x: 7 }),
+            (0xF785, Instruction::LoadRegistersFromRplFlags { x: 7 }),
+        ];
+        for (opcode, instruction) in cases {
+            assert_eq!(decode(opcode, None), Ok(instruction), "{:04X}", opcode);
+            assert_eq!(instruction.size(), 2, "{:04X}", opcode);
+        }
+    }
+
+    #[test]
+    fn decodes_long_address_from_next_word() {
+        let instruction = decode(0xF000, Some(0x1234));
+        assert_eq!(
+            instruction,
+            Ok(Instruction::SetIToLongAddress { nnnn: 0x1234 })
+        );
+        assert_eq!(instruction.unwrap().size(), 4);
+        assert_eq!(decode(0xF000, None), Err(DecodeError { opcode: 0xF000 }));
+    }
+
+    #[test]
+    fn rejects_unknown_low_nibbles() {
+        for opcode in [
+            0x5AB1, 0x5AB4, 0x8AB8, 0x8ABF, 0x9AB1, 0x9ABF, 0xEA00, 0xFA00, 0xFA02, 0xFAFF,
+        ] {
+            assert_eq!(decode(opcode, Some(0)), Err(DecodeError { opcode }));
+        }
+    }
+
+    #[test]
+    fn decodes_other_0nnn_as_machine_calls() {
+        for nnn in [0x000, 0x0E1, 0x0EF, 0x0FA, 0x1E0, 0xFFF] {
+            assert_eq!(decode(nnn, None), Ok(Instruction::MachineCall { nnn }));
+        }
+    }
+
+    #[test]
+    fn orders_instruction_sets() {
+        let set = |opcode| decode(opcode, Some(0)).unwrap().instruction_set();
+        assert_eq!(set(0x00E0), InstructionSet::Chip8);
+        assert_eq!(set(0x00FD), InstructionSet::SuperChip10);
+        assert_eq!(set(0xFA75), InstructionSet::SuperChip10);
+        assert_eq!(set(0x00FB), InstructionSet::SuperChip);
+        assert_eq!(set(0xFA30), InstructionSet::SuperChip);
+        assert_eq!(set(0x00D1), InstructionSet::XoChip);
+        assert_eq!(set(0xF000), InstructionSet::XoChip);
+    }
+
+    #[test]
+    fn decodes_later_0nnn_as_machine_calls() {
+        for opcode in [0x00C5, 0x00D1, 0x00FB, 0x00FC, 0x00FD, 0x00FE, 0x00FF] {
+            let nnn = opcode & 0x0FFF;
+            assert_eq!(
+                decode_for(opcode, None, InstructionSet::Chip8),
+                Ok(Instruction::MachineCall { nnn })
+            );
+        }
+        assert_eq!(
+            decode_for(0x00D1, None, InstructionSet::SuperChip),
+            Ok(Instruction::MachineCall { nnn: 0x0D1 })
+        );
+        assert_eq!(
+            decode_for(0x00FB, None, InstructionSet::SuperChip),
+            Ok(Instruction::ScrollRight)
+        );
+        assert_eq!(
+            decode_for(0xFA75, None, InstructionSet::Chip8),
+            Err(DecodeError { opcode: 0xFA75 })
+        );
+        assert_eq!(
+            decode_for(0xF000, Some(0), InstructionSet::SuperChip),
+            Err(DecodeError { opcode: 0xF000 })
+        );
+    }
+}
@@ -1,4 +1,24 @@
-pub mod cpu;
+pub mod audio;
+mod chip8;
+mod cpu;
+pub mod disasm;
+mod error;
 pub mod font;
 pub mod graphics;
-pub mod ops;
+pub mod image;
+mod instruction;
+pub mod movie;
+mod ops;
+mod platform;
+pub mod rewind;
+mod rng;
+mod state;
+pub mod storage;
+
+pub use chip8::Chip8;
+pub use cpu::{Options, FRAME_RATE};
+pub use error::{Chip8Error, DecodeError, MovieError, StateError};
+pub use font::FontSet;
+pub use instruction::{decode, decode_for, Instruction};
+pub use platform::{InstructionSet, Platform};
+pub use state::STATE_VERSION;
@@ -0,0 +1,222 @@
+use crate::chip8::Chip8;
+use crate::cpu::Options;
+use crate::error::{Chip8Error, MovieError};
+use crate::state::{read_options, write_options, Reader, Writer};
+
+/*
+|  Movie format, all numbers little-endian
+|
+|  magic "C8MV", version (u8), ROM hash (u64), options as in save states,
+|  RNG seed (u64), cycles per frame (u32), frame count (u32), then per frame
+|  the keypad (u16, bit N is key N) and the state hash after the frame (u64).
+*/
+const MAGIC: &[u8; 4] = b"C8MV";
+pub const MOVIE_VERSION: u8 = 3;
+
+// The keypad during a frame and the machine state hash after it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MovieFrame {
+    pub keys: u16,
+    pub state_hash: u64,
+}
+
+// A recorded play session, replayed by starting a machine with the same
+// ROM, options and seed and feeding it the same keys every frame
+#[derive(Clone, Debug)]
+pub struct Movie {
+    pub rom_hash: u64,
+    pub options: Options,
+    pub seed: u64,
+    pub cycles_per_frame: u32,
+    pub frames: Vec<MovieFrame>,
+}
+
+impl Movie {
+    // Starts recording `chip8`, which must have just been seeded with `seed`
+    pub fn new(chip8: &Chip8, seed: u64) -> Movie {
+        Movie {
+            rom_hash: chip8.rom_hash(),
+            options: *chip8.options(),
+            seed,
+            cycles_per_frame: chip8.cycles_per_frame(),
+            frames: Vec::new(),
+        }
+    }
+
+    // Records the frame `chip8` just ran
+    pub fn record_frame(&mut self, chip8: &Chip8) {
+        let keys = chip8
+            .keypad()
+            .iter()
+            .enumerate()
+            .fold(0, |keys, (key, &pressed)| keys | (pressed as u16) << key);
+        self.frames.push(MovieFrame {
+            keys,
+            state_hash: chip8.state_hash(),
+        });
+    }
+
+    // Prepares `chip8`, created with the options of the movie and with the
+    // ROM loaded, for replaying from the first frame
+    pub fn start(&self, chip8: &mut Chip8) -> Result<(), MovieError> {
+        if chip8.rom_hash() != self.rom_hash {
+            return Err(MovieError::RomMismatch);
+        }
+        chip8.set_seed(self.seed);
+        chip8.set_cycles_per_frame(self.cycles_per_frame);
+        Ok(())
+    }
+
+    // Runs frame `frame` of the movie on `chip8`, returns whether the machine
+    // is still in sync with the recording
+    pub fn replay_frame(&self, frame: usize, chip8: &mut Chip8) -> Result<bool, Chip8Error> {
+        let recorded = self.frames[frame];
+        for key in 0..16 {
+            chip8.set_key(key, recorded.keys & (1 << key) != 0);
+        }
+        chip8.run_frame()?;
+        Ok(chip8.state_hash() == recorded.state_hash)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = Writer(Vec::with_capacity(64 + self.frames.len() * 10));
+        w.bytes(MAGIC);
+        w.u8(MOVIE_VERSION);
+        w.u64(self.rom_hash);
+        write_options(&mut w, &self.options);
+        w.u64(self.seed);
+        w.u32(self.cycles_per_frame);
+        w.u32(self.frames.len() as u32);
+        for frame in &self.frames {
+            w.u16(frame.keys);
+            w.u64(frame.state_hash);
+        }
+        w.0
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Movie, MovieError> {
+        let mut r = Reader(bytes);
+        if r.bytes(MAGIC.len())? != MAGIC {
+            return Err(MovieError::InvalidFormat);
+        }
+        let version = r.u8()?;
+        if version != MOVIE_VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+        let rom_hash = r.u64()?;
+        let options = read_options(&mut r)?;
+        let seed = r.u64()?;
+        let cycles_per_frame = r.u32()?;
+        let count = r.u32()? as usize;
+        let mut frames = Vec::with_capacity(count.min(bytes.len() / 10));
+        for _ in 0..count {
+            frames.push(MovieFrame {
+                keys: r.u16()?,
+                state_hash: r.u64()?,
+            });
+        }
+        Ok(Movie {
+            rom_hash,
+            options,
+            seed,
+            cycles_per_frame,
+            frames,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Rolls V0, counts frames without key 0 held in V2
+    const ROM: [u8; 8] = [0xC0, 0xFF, 0xE1, 0x9E, 0x72, 0x01, 0x12, 0x00];
+
+    fn machine(options: Options, rom: &[u8]) -> Chip8 {
+        let mut chip8 = Chip8::headless(options).unwrap();
+        chip8.load_rom(rom).unwrap();
+        chip8
+    }
+
+    // Records 30 frames with key 0 held from frame 10 to 19
+    fn record() -> (Movie, Chip8) {
+        let mut chip8 = machine(Options::default(), &ROM);
+        chip8.set_seed(99);
+        let mut movie = Movie::new(&chip8, 99);
+        for frame in 0..30 {
+            chip8.set_key(0, (10..20).contains(&frame));
+            chip8.run_frame().unwrap();
+            movie.record_frame(&chip8);
+        }
+        (movie, chip8)
+    }
+
+    #[test]
+    fn round_trips_bytes() {
+        let (movie, _) = record();
+        let bytes = movie.to_bytes();
+        let read = Movie::from_bytes(&bytes).unwrap();
+        assert_eq!(read.rom_hash, movie.rom_hash);
+        assert_eq!(read.seed, 99);
+        assert_eq!(read.cycles_per_frame, movie.cycles_per_frame);
+        assert_eq!(read.frames, movie.frames);
+        assert_eq!(read.frames[15].keys, 1);
+        assert_eq!(read.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn replays_in_sync() {
+        let (movie, recorded) = record();
+        let mut chip8 = machine(movie.options, &ROM);
+        movie.start(&mut chip8).unwrap();
+        for frame in 0..movie.frames.len() {
+            assert_eq!(movie.replay_frame(frame, &mut chip8), Ok(true), "{}", frame);
+        }
+        assert_eq!(chip8.state_hash(), recorded.state_hash());
+        assert_eq!(chip8.v(2), recorded.v(2));
+    }
+
+    #[test]
+    fn detects_desync() {
+        let (mut movie, _) = record();
+        movie.frames[5].keys = 1;
+        let mut chip8 = machine(movie.options, &ROM);
+        movie.start(&mut chip8).unwrap();
+        for frame in 0..5 {
+            assert_eq!(movie.replay_frame(frame, &mut chip8), Ok(true));
+        }
+        assert_eq!(movie.replay_frame(5, &mut chip8), Ok(false));
+    }
+
+    #[test]
+    fn rejects_invalid_movies() {
+        let bytes = record().0.to_bytes();
+
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] = b'X';
+        assert_eq!(
+            Movie::from_bytes(&bad_magic).err(),
+            Some(MovieError::InvalidFormat)
+        );
+
+        let mut old = bytes.clone();
+        old[MAGIC.len()] = MOVIE_VERSION - 1;
+        assert_eq!(
+            Movie::from_bytes(&old).err(),
+            Some(MovieError::UnsupportedVersion(MOVIE_VERSION - 1))
+        );
+
+        let truncated = &bytes[..bytes.len() - 1];
+        assert_eq!(
+            Movie::from_bytes(truncated).err(),
+            Some(MovieError::InvalidFormat)
+        );
+    }
+
+    #[test]
+    fn rejects_another_rom() {
+        let (movie, _) = record();
+        let mut chip8 = machine(movie.options, &[0x12, 0x00]);
+        assert_eq!(movie.start(&mut chip8), Err(MovieError::RomMismatch));
+    }
+}
@@ -1,44 +1,114 @@
-use crate::lib::cpu::CPU;
-use crate::lib::graphics::{HEIGHT, WIDTH};
+use crate::cpu::CPU;
+use crate::error::Fault;
+use crate::font::{LARGE_GLYPH_SIZE, SMALL_GLYPH_SIZE};
+use crate::graphics::{ALL_PLANES, FIRST_PLANE, SECOND_PLANE};
+use crate::platform::InstructionSet;
 
 /*
-|  UNKNOWN OPCODE (Panic when encountered)
+|  UNKNOWN OPCODE (Fail when encountered)
 */
-pub fn unknown_opcode(opcode: u16) {
-    panic!("Unknown opcode: {:X}", opcode);
+pub fn unknown_opcode() -> Result<(), Fault> {
+    Err(Fault::UnknownOpcode)
+}
+/*
+|  00CN - SCD NIBBLE (Scroll the display down by N pixels) [SUPER-CHIP 1.1]
+|
+|  The interpreter moves every row of the display N pixels down. Rows that
+|  scroll in at the top are blank.
+*/
+pub fn scroll_down(cpu: &mut CPU, n: u8) -> Result<(), Fault> {
+    cpu.pixels.scroll_down(n as usize, cpu.planes);
+    Ok(())
+}
+/*
+|  00DN - SCU NIBBLE (Scroll the display up by N pixels) [XO-CHIP]
+|
+|  The interpreter moves every row of the display N pixels up. Rows that
+|  scroll in at the bottom are blank.
+*/
+pub fn scroll_up(cpu: &mut CPU, n: u8) -> Result<(), Fault> {
+    cpu.pixels.scroll_up(n as usize, cpu.planes);
+    Ok(())
 }
 /*
 |  00E0 - CLS (Clear the display)
+|
+|  XO-CHIP only clears the selected bitplanes.
 */
-pub fn clear_screen(cpu: &mut CPU) {
-    cpu.display.clear();
+pub fn clear_screen(cpu: &mut CPU) -> Result<(), Fault> {
+    cpu.pixels.clear_planes(cpu.planes);
+    Ok(())
 }
 /*
 |  00EE - RET (Return from a subroutine)
 |
 |  The interpreter pops the last address from the stack and sets the PC to
-|  it.
+|  it. Returning with an empty stack is an underflow.
+*/
+pub fn return_from_subroutine(cpu: &mut CPU) -> Result<(), Fault> {
+    cpu.pc = cpu.pop()?;
+    Ok(())
+}
+/*
+|  00FB - SCR (Scroll the display right by 4 pixels) [SUPER-CHIP 1.1]
+|
+|  The interpreter moves every column of the display 4 pixels to the right.
+|  Columns that scroll in at the left are blank.
+*/
+pub fn scroll_right(cpu: &mut CPU) -> Result<(), Fault> {
+    cpu.pixels.scroll_right(4, cpu.planes);
+    Ok(())
+}
+/*
+|  00FC - SCL (Scroll the display left by 4 pixels) [SUPER-CHIP 1.1]
+|
+|  The interpreter moves every column of the display 4 pixels to the left.
+|  Columns that scroll in at the right are blank.
 */
-pub fn return_from_subroutine(cpu: &mut CPU) {
-    cpu.pc = cpu.stack.pop().unwrap();
+pub fn scroll_left(cpu: &mut CPU) -> Result<(), Fault> {
+    cpu.pixels.scroll_left(4, cpu.planes);
+    Ok(())
+}
+/*
+|  00FD - EXIT (Exit the interpreter) [SUPER-CHIP]
+|
+|  The interpreter stops executing instructions.
+*/
+pub fn exit(cpu: &mut CPU) -> Result<(), Fault> {
+    cpu.halted = true;
+    Ok(())
+}
+/*
+|  00FE - LOW (Switch to 64 x 32 low resolution) [SUPER-CHIP]
+|  00FF - HIGH (Switch to 128 x 64 high resolution) [SUPER-CHIP]
+|
+|  The interpreter switches the display resolution and clears it.
+*/
+pub fn set_resolution(cpu: &mut CPU, hires: bool) -> Result<(), Fault> {
+    cpu.pixels.set_hires(hires);
+    Ok(())
 }
 /*
 |  1NNN - JP NNN (Jump to address NNN)
 |
 |  The interpreter sets the program counter to nnn.
 */
-pub fn jump_to_address(cpu: &mut CPU, address: u16) {
+pub fn jump_to_address(cpu: &mut CPU, address: u16) -> Result<(), Fault> {
     cpu.pc = address;
+    Ok(())
 }
 /*
 |  2NNN - CALL NNN (Call subroutine at NNN)
 |
 |  The interpreter puts the current PC on the top of the stack. The PC is
-|  then set to nnn.
+|  then set to nnn. Calling with a full stack is an overflow. The COSMAC
+|  VIP keeps the stack in memory, growing down from 0xECF, where ROMs can
+|  read and change it.
 */
-pub fn call_subroutine(cpu: &mut CPU, address: u16) {
-    cpu.stack.push(cpu.pc);
+pub fn call_subroutine(cpu: &mut CPU, address: u16) -> Result<(), Fault> {
+    cpu.push(cpu.pc)?;
     cpu.pc = address;
+    Ok(())
 }
 /*
 |  3XNN - SE VX, BYTE (Skip next instruction if VX equals NN)
@@ -46,10 +116,11 @@ pub fn call_subroutine(cpu: &mut CPU, address: u16) {
 |  The interpreter compares register VX to NN, and if they are equal,
 |  increments the program counter by 2.
 */
-pub fn skip_next_if_vx_equals_nn(cpu: &mut CPU, x: u8, nn: u8) {
+pub fn skip_next_if_vx_equals_nn(cpu: &mut CPU, x: u8, nn: u8) -> Result<(), Fault> {
     if cpu.v[x as usize] == nn {
-        cpu.pc += 2;
+        cpu.skip_next();
     }
+    Ok(())
 }
 /*
 |  4XNN - SNE VX, BYTE (Skip next instruction if VX doesn't equal NN)
@@ -57,10 +128,11 @@ pub fn skip_next_if_vx_equals_nn(cpu: &mut CPU, x: u8, nn: u8) {
 |  The interpreter compares register VX to NN, and if they are not equal,
 |  increments the program counter by 2.
 */
-pub fn skip_next_if_vx_not_equals_nn(cpu: &mut CPU, x: u8, nn: u8) {
+pub fn skip_next_if_vx_not_equals_nn(cpu: &mut CPU, x: u8, nn: u8) -> Result<(), Fault> {
     if cpu.v[x as usize] != nn {
-        cpu.pc += 2;
+        cpu.skip_next();
     }
+    Ok(())
 }
 /*
 |  5XY0 - SE VX, VY (Skip next instruction if VX equals VY)
@@ -68,67 +140,118 @@ pub fn skip_next_if_vx_not_equals_nn(cpu: &mut CPU, x: u8, nn: u8) {
 |  The interpreter compares register VX to register VY, and if they are
 |  equal, increments the program counter by 2.
 */
-pub fn skip_next_if_vx_equals_vy(cpu: &mut CPU, x: u8, y: u8) {
+pub fn skip_next_if_vx_equals_vy(cpu: &mut CPU, x: u8, y: u8) -> Result<(), Fault> {
     if cpu.v[x as usize] == cpu.v[y as usize] {
-        cpu.pc += 2;
+        cpu.skip_next();
     }
+    Ok(())
+}
+/*
+|  5XY2 - SAVE VX - VY (Store registers VX through VY in memory starting at location I) [XO-CHIP]
+|
+|  The interpreter copies the values of registers VX through VY into
+|  memory, starting at the address in I. If X is larger than Y the
+|  registers are stored in reverse order. I is not changed.
+*/
+pub fn store_vx_to_vy_in_memory(cpu: &mut CPU, x: u8, y: u8) -> Result<(), Fault> {
+    for (offset, register) in register_range(x, y).enumerate() {
+        cpu.write(cpu.i as usize + offset, cpu.v[register])?;
+    }
+    Ok(())
+}
+/*
+|  5XY3 - LOAD VX - VY (Read registers VX through VY from memory starting at location I) [XO-CHIP]
+|
+|  The interpreter reads values from memory starting at location I into
+|  registers VX through VY. If X is larger than Y the registers are loaded
+|  in reverse order. I is not changed.
+*/
+pub fn load_vx_to_vy_from_memory(cpu: &mut CPU, x: u8, y: u8) -> Result<(), Fault> {
+    for (offset, register) in register_range(x, y).enumerate() {
+        cpu.v[register] = cpu.read(cpu.i as usize + offset)?;
+    }
+    Ok(())
+}
+// Registers X through Y, counting down when X is larger than Y
+fn register_range(x: u8, y: u8) -> impl Iterator<Item = usize> {
+    let (x, y) = (x as usize, y as usize);
+    (0..=x.abs_diff(y)).map(move |offset| if x <= y { x + offset } else { x - offset })
 }
 /*
 |  6XNN - LD VX, BYTE (Set VX to NN)
 |
 |  The interpreter puts the value NN into register VX.
 */
-pub fn set_vx_to_nn(cpu: &mut CPU, x: u8, nn: u8) {
+pub fn set_vx_to_nn(cpu: &mut CPU, x: u8, nn: u8) -> Result<(), Fault> {
     cpu.v[x as usize] = nn;
+    Ok(())
 }
 /*
 |  7XNN - ADD VX, BYTE (Add NN to VX)
 |
 |  Adds the value NN to the value of register VX, then stores the result in VX.
 */
-pub fn add_nn_to_vx(cpu: &mut CPU, x: u8, nn: u8) {
+pub fn add_nn_to_vx(cpu: &mut CPU, x: u8, nn: u8) -> Result<(), Fault> {
     cpu.v[x as usize] = cpu.v[x as usize].wrapping_add(nn);
+    Ok(())
 }
 /*
 |  8XY0 - LD VX, VY (Set VX to VY)
 |
 |  Stores the value of register VY in register VX.
 */
-pub fn set_vx_to_vy(cpu: &mut CPU, x: u8, y: u8) {
+pub fn set_vx_to_vy(cpu: &mut CPU, x: u8, y: u8) -> Result<(), Fault> {
     cpu.v[x as usize] = cpu.v[y as usize];
+    Ok(())
 }
 /*
+|  !AMBIGUOUS!
 |  8XY1 - OR VX, VY (Set VX to VX or VY)
 |
 |  Performs a bitwise OR on the values of VX and VY, then stores the result
 |  in VX. A bitwise OR compares the corrseponding bits from two values, and
 |  if either bit is 1, then the same bit in the result is also 1.
-|  Otherwise, it is 0.
+|  Otherwise, it is 0. The COSMAC VIP also resets VF to 0.
 */
-pub fn set_vx_to_vx_or_vy(cpu: &mut CPU, x: u8, y: u8) {
+pub fn set_vx_to_vx_or_vy(cpu: &mut CPU, x: u8, y: u8) -> Result<(), Fault> {
     cpu.v[x as usize] |= cpu.v[y as usize];
+    if cpu.options.reset_vf_on_logic_ops {
+        cpu.v[0xF] = 0;
+    }
+    Ok(())
 }
 /*
+|  !AMBIGUOUS!
 |  8XY2 - AND VX, VY (Set VX to VX and VY)
 |
 |  Performs a bitwise AND on the values of VX and VY, then stores the
 |  result in VX. A bitwise AND compares the corrseponding bits from two
 |  values, and if both bits are 1, then the same bit in the result is also
-|  1. Otherwise, it is 0.
+|  1. Otherwise, it is 0. The COSMAC VIP also resets VF to 0.
 */
-pub fn set_vx_to_vx_and_vy(cpu: &mut CPU, x: u8, y: u8) {
+pub fn set_vx_to_vx_and_vy(cpu: &mut CPU, x: u8, y: u8) -> Result<(), Fault> {
     cpu.v[x as usize] &= cpu.v[y as usize];
+    if cpu.options.reset_vf_on_logic_ops {
+        cpu.v[0xF] = 0;
+    }
+    Ok(())
 }
 /*
+|  !AMBIGUOUS!
 |  8XY3 - XOR VX, VY (Set VX to VX xor VY)
 |
 |  Performs a bitwise exclusive OR on the values of VX and VY, then stores
 |  the result in VX. An exclusive OR compares the corrseponding bits from
 |  two values, and if the bits are not both the same, then the
-|  corresponding bit in the result is set to 1. Otherwise, it is 0.
+|  corresponding bit in the result is set to 1. Otherwise, it is 0. The
+|  COSMAC VIP also resets VF to 0.
 */
-pub fn set_vx_to_vx_xor_vy(cpu: &mut CPU, x: u8, y: u8) {
+pub fn set_vx_to_vx_xor_vy(cpu: &mut CPU, x: u8, y: u8) -> Result<(), Fault> {
     cpu.v[x as usize] ^= cpu.v[y as usize];
+    if cpu.options.reset_vf_on_logic_ops {
+        cpu.v[0xF] = 0;
+    }
+    Ok(())
 }
 /*
 |  8XY4 - ADD VX, VY (Set VX to VX + VY, set VF to carry)
@@ -137,10 +260,11 @@ pub fn set_vx_to_vx_xor_vy(cpu: &mut CPU, x: u8, y: u8) {
 |  than 8 bits (i.e., > 255,) VF is set to 1, otherwise 0. Only the lowest
 |  8 bits of the result are kept, and stored in VX.
 */
-pub fn add_vy_to_vx(cpu: &mut CPU, x: u8, y: u8) {
+pub fn add_vy_to_vx(cpu: &mut CPU, x: u8, y: u8) -> Result<(), Fault> {
     let (result, carry) = cpu.v[x as usize].overflowing_add(cpu.v[y as usize]);
     cpu.v[x as usize] = result;
     cpu.v[0xF] = if carry { 1 } else { 0 };
+    Ok(())
 }
 /*
 |  8XY5 - SUB VX, VY (Set VX to VX - VY, set VF to NOT borrow)
@@ -148,10 +272,11 @@ pub fn add_vy_to_vx(cpu: &mut CPU, x: u8, y: u8) {
 |  If VX > VY, then VF is set to 1, otherwise 0. Then VY is subtracted from
 |  VX, and the results stored in VX.
 */
-pub fn set_vx_to_vx_minus_vy(cpu: &mut CPU, x: u8, y: u8) {
+pub fn set_vx_to_vx_minus_vy(cpu: &mut CPU, x: u8, y: u8) -> Result<(), Fault> {
     let (result, borrow) = cpu.v[x as usize].overflowing_sub(cpu.v[y as usize]);
     cpu.v[x as usize] = result;
     cpu.v[0xF] = if borrow { 0 } else { 1 };
+    Ok(())
 }
 /*
 |  !AMBIGUOUS!
@@ -160,12 +285,14 @@ pub fn set_vx_to_vx_minus_vy(cpu: &mut CPU, x: u8, y: u8) {
 |  If the least-significant bit of VX is 1, then VF is set to 1, otherwise
 |  0. Then VX is divided by 2.
 */
-pub fn shift_vx_right_by_one(cpu: &mut CPU, x: u8, y: u8) {
+pub fn shift_vx_right_by_one(cpu: &mut CPU, x: u8, y: u8) -> Result<(), Fault> {
     if cpu.options.put_value_of_vy_into_vx_before_shifting {
         cpu.v[x as usize] = cpu.v[y as usize];
     }
-    cpu.v[0xF] = cpu.v[x as usize] & 0x000F;
+    let flag = cpu.v[x as usize] & 0x01;
     cpu.v[x as usize] >>= 1;
+    cpu.v[0xF] = flag;
+    Ok(())
 }
 /*
 |  8XY7 - SUBN VX, VY (Set VX to VY - VX, set VF to NOT borrow)
@@ -173,10 +300,11 @@ pub fn shift_vx_right_by_one(cpu: &mut CPU, x: u8, y: u8) {
 |  If VY > VX, then VF is set to 1, otherwise 0. Then VX is subtracted from
 |  VY, and the results stored in VX.
 */
-pub fn set_vx_to_vy_minus_vx(cpu: &mut CPU, x: u8, y: u8) {
+pub fn set_vx_to_vy_minus_vx(cpu: &mut CPU, x: u8, y: u8) -> Result<(), Fault> {
     let (result, borrow) = cpu.v[y as usize].overflowing_sub(cpu.v[x as usize]);
     cpu.v[x as usize] = result;
     cpu.v[0xF] = if borrow { 0 } else { 1 };
+    Ok(())
 }
 /*
 |  !AMBIGUOUS!
@@ -185,12 +313,14 @@ pub fn set_vx_to_vy_minus_vx(cpu: &mut CPU, x: u8, y: u8) {
 |  If the most-significant bit of VX is 1, then VF is set to 1, otherwise
 |  to 0. Then VX is multiplied by 2.
 */
-pub fn shift_vx_left_by_one(cpu: &mut CPU, x: u8, y: u8) {
+pub fn shift_vx_left_by_one(cpu: &mut CPU, x: u8, y: u8) -> Result<(), Fault> {
     if cpu.options.put_value_of_vy_into_vx_before_shifting {
         cpu.v[x as usize] = cpu.v[y as usize];
     }
-    cpu.v[0xF] = cpu.v[x as usize] & 0xF0;
+    let flag = cpu.v[x as usize] >> 7;
     cpu.v[x as usize] <<= 1;
+    cpu.v[0xF] = flag;
+    Ok(())
 }
 /*
 |  9XY0 - SNE VX, VY (Skip next instruction if VX != VY)
@@ -198,18 +328,20 @@ pub fn shift_vx_left_by_one(cpu: &mut CPU, x: u8, y: u8) {
 |  The values of VX and VY are compared, and if they are not equal, the
 |  program counter is increased by 2.
 */
-pub fn skip_next_if_vx_not_equals_vy(cpu: &mut CPU, x: u8, y: u8) {
+pub fn skip_next_if_vx_not_equals_vy(cpu: &mut CPU, x: u8, y: u8) -> Result<(), Fault> {
     if cpu.v[x as usize] != cpu.v[y as usize] {
-        cpu.pc += 2;
+        cpu.skip_next();
     }
+    Ok(())
 }
 /*
 |  ANNN - LD I, ADDR (Set I to NNN)
 |
 |  The value of register I is set to NNN.
 */
-pub fn set_i_to_nnn(cpu: &mut CPU, nnn: u16) {
+pub fn set_i_to_nnn(cpu: &mut CPU, nnn: u16) -> Result<(), Fault> {
     cpu.i = nnn;
+    Ok(())
 }
 /*
 |  !AMBIGUOUS!
@@ -217,72 +349,133 @@ pub fn set_i_to_nnn(cpu: &mut CPU, nnn: u16) {
 |
 |  The program counter is set to NNN plus the value of V0.
 */
-pub fn jump_to_address_plus_v0(cpu: &mut CPU, x: u8, address: u16) {
+pub fn jump_to_address_plus_v0(cpu: &mut CPU, x: u8, address: u16) -> Result<(), Fault> {
     if cpu.options.jump_to_nnn_plus_the_value_in_v0 {
         cpu.pc = address + cpu.v[0x0] as u16;
     } else {
         cpu.pc = address + cpu.v[x as usize] as u16;
     }
+    Ok(())
 }
 /*
 |  CXNN - RND VX, BYTE (Set VX to a random number AND NN)
 |
 |  The interpreter generates a random number from 0 to 255, which is then
 |  ANDed with the value NN. The results are stored in VX. See instruction
-|  8XY2 for more information on AND.
+|  8XY2 for more information on AND. The numbers come from the seeded
+|  generator of the CPU.
 */
-pub fn set_vx_to_random_number_and_nn(cpu: &mut CPU, x: u8, nn: u8) {
-    cpu.v[x as usize] = rand::random::<u8>() & nn;
+pub fn set_vx_to_random_number_and_nn(cpu: &mut CPU, x: u8, nn: u8) -> Result<(), Fault> {
+    cpu.v[x as usize] = cpu.rng.next_byte() & nn;
+    Ok(())
 }
 /*
+|  !AMBIGUOUS!
 |  DXYN - DRW VX, VY, NIBBLE (Display N-byte sprite starting at memory location I at (VX, VY), set VF = collision)
 |
 |  The interpreter reads N bytes from memory, starting at the address
 |  stored in I. These bytes are then displayed as sprites on screen at
 |  coordinates (VX, VY). Sprites are XORed onto the existing screen. If
 |  this causes any pixels to be erased, VF is set to 1, otherwise it is set
-|  to 0. If the sprite is positioned so part of it is outside the
-|  coordinates of the display, it wraps around to the opposite side of the
-|  screen. See instruction 8XY3 for more information on XOR.
+|  to 0. The starting coordinates always wrap around the display. If the
+|  sprite is positioned so part of it is outside the coordinates of the
+|  display, the COSMAC VIP clips it at the edge while XO-CHIP wraps it
+|  around to the opposite side of the screen. See instruction 8XY3 for more
+|  information on XOR. The COSMAC VIP waits for the vertical blank before
+|  drawing, so at most one sprite is drawn per frame.
+|
+|  DXY0 - DRW VX, VY, 0 (Display 16x16 sprite) [SUPER-CHIP]
+|
+|  Draws a 16x16 sprite made of 32 bytes, two bytes per row. In high
+|  resolution SUPER-CHIP 1.x sets VF to the number of rows that collided or
+|  were clipped at the bottom of the display. SUPER-CHIP 1.0 draws an 8x16
+|  sprite of 16 bytes in low resolution.
+|
+|  XO-CHIP draws the sprite on every selected bitplane, reading the data
+|  for the second plane right after the data for the first.
 */
-pub fn draw_sprite(cpu: &mut CPU, x: u8, y: u8, n: u8) {
-    // Get x and y coords
-    let x_coord = cpu.v[x as usize] as usize % WIDTH;
-    let y_coord = cpu.v[y as usize] as usize % HEIGHT;
+pub fn draw_sprite(cpu: &mut CPU, x: u8, y: u8, n: u8) -> Result<(), Fault> {
+    let width = cpu.pixels.width();
+    let height = cpu.pixels.height();
 
-    // Unset flag register
-    cpu.v[0xF] = 0;
+    // Get x and y coords, wrapped to the display
+    let x_coord = cpu.v[x as usize] as usize % width;
+    let y_coord = cpu.v[y as usize] as usize % height;
 
-    let mut j = 0;
+    // Get the size of the sprite
+    let instruction_set = cpu.options.instruction_set;
+    let (rows, cols) = match n {
+        0 if instruction_set == InstructionSet::SuperChip10 && !cpu.pixels.is_hires() => (16, 8),
+        0 if instruction_set >= InstructionSet::SuperChip10 => (16, 16),
+        _ => (n as usize, 8),
+    };
+    let bytes_per_row = cols / 8;
 
-    // For each row in height of sprite
-    for row in 0..n {
-        // Stop drawing this row if we reach the end of the screen
-        if y_coord + n as usize >= HEIGHT {
-            break;
+    let mut collided_rows = 0;
+    let mut clipped_rows = 0;
+
+    // Each selected bitplane gets its own sprite data, one after the other
+    let mut address = cpu.i as usize;
+    for plane in [FIRST_PLANE, SECOND_PLANE] {
+        if cpu.planes & plane == 0 {
+            continue;
         }
 
-        // Get sprite from memory
-        let sprite = cpu.memory[(cpu.i + j) as usize];
+        // For each row in height of sprite
+        for row in 0..rows {
+            // Clip or wrap the row at the bottom of the screen
+            let mut pixel_y = y_coord + row;
+            if pixel_y >= height {
+                if cpu.options.clip_sprites {
+                    clipped_rows = rows - row;
+                    break;
+                }
+                pixel_y %= height;
+            }
 
-        // For each column in sprite width (8)
-        for col in 0..8 {
-            // Stop drawing this column if we reach the end of the screen
-            if x_coord + col >= WIDTH {
-                break;
+            // Get sprite row from memory
+            let mut sprite = 0u16;
+            for byte in 0..bytes_per_row {
+                sprite = sprite << 8 | cpu.read(address + row * bytes_per_row + byte)? as u16;
             }
 
-            // Set pixel on/off
-            let old_pixel = cpu.pixels[y_coord + row as usize][x_coord + col];
-            let new_pixel = (sprite & (1 << (7 - col))) != 0;
-            cpu.pixels[y_coord + row as usize][x_coord + col] = old_pixel ^ new_pixel;
-            cpu.v[0xF] |= cpu.pixels[y_coord + row as usize][x_coord + col] as u8;
-        }
+            // For each column in sprite width
+            let mut collided = false;
+            for col in 0..cols {
+                // Clip or wrap the column at the right of the screen
+                let mut pixel_x = x_coord + col;
+                if pixel_x >= width {
+                    if cpu.options.clip_sprites {
+                        break;
+                    }
+                    pixel_x %= width;
+                }
 
-        j += 1;
+                // Flip the pixel, a pixel that is turned off is a collision
+                if sprite & (1 << (cols - 1 - col)) != 0 && cpu.pixels.flip(pixel_x, pixel_y, plane)
+                {
+                    collided = true;
+                }
+            }
+            if collided {
+                collided_rows += 1;
+            }
+        }
+        address += rows * bytes_per_row;
     }
 
-    cpu.display.draw(&cpu.pixels);
+    // Set flag register
+    cpu.v[0xF] = if cpu.options.count_collided_rows && cpu.pixels.is_hires() {
+        (collided_rows + clipped_rows) as u8
+    } else {
+        (collided_rows > 0) as u8
+    };
+
+    // Idle until the next frame
+    if cpu.options.wait_for_vblank {
+        cpu.waiting_for_vblank = true;
+    }
+    Ok(())
 }
 /*
 |  EX9E - SKP VX (Skip next instruction if key with the value of VX is pressed)
@@ -290,8 +483,11 @@ pub fn draw_sprite(cpu: &mut CPU, x: u8, y: u8, n: u8) {
 |  Checks the keyboard, and if the key corresponding to the value of Vx is
 |  currently in the down position, PC is increased by 2.
 */
-pub fn skip_next_if_key_is_pressed(cpu: &mut CPU, x: u8) {
-    //
+pub fn skip_next_if_key_is_pressed(cpu: &mut CPU, x: u8) -> Result<(), Fault> {
+    if cpu.keys[(cpu.v[x as usize] & 0xF) as usize] {
+        cpu.skip_next();
+    }
+    Ok(())
 }
 /*
 |  EXA1 - SKNP VX (Skip next instruction if key with the value of VX is not pressed)
@@ -299,49 +495,128 @@ pub fn skip_next_if_key_is_pressed(cpu: &mut CPU, x: u8) {
 |  Checks the keyboard, and if the key corresponding to the value of VX is
 |  currently in the up position, PC is increased by 2.
 */
-pub fn skip_next_if_key_is_not_pressed(cpu: &mut CPU, x: u8) {
-    //
+pub fn skip_next_if_key_is_not_pressed(cpu: &mut CPU, x: u8) -> Result<(), Fault> {
+    if !cpu.keys[(cpu.v[x as usize] & 0xF) as usize] {
+        cpu.skip_next();
+    }
+    Ok(())
+}
+/*
+|  F000 NNNN - LD I, LONG NNNN (Set I to the 16-bit address NNNN) [XO-CHIP]
+|
+|  The value of I is set to the word following the instruction, which is
+|  skipped.
+*/
+pub fn set_i_to_long_address(cpu: &mut CPU, nnnn: u16) -> Result<(), Fault> {
+    cpu.i = nnnn;
+    cpu.pc = cpu.pc.wrapping_add(2);
+    Ok(())
+}
+/*
+|  FN01 - PLANE N (Select the bitplanes N to draw on) [XO-CHIP]
+|
+|  Bit 0 of N selects the first plane and bit 1 the second. Clearing,
+|  scrolling and drawing only affect the selected planes.
+*/
+pub fn select_planes(cpu: &mut CPU, n: u8) -> Result<(), Fault> {
+    cpu.planes = n & ALL_PLANES;
+    Ok(())
+}
+/*
+|  F002 - AUDIO (Load the audio pattern from memory starting at location I) [XO-CHIP]
+|
+|  The interpreter copies 16 bytes at I into the audio pattern buffer,
+|  which is played instead of the tone while the sound timer is active.
+*/
+pub fn load_pattern(cpu: &mut CPU) -> Result<(), Fault> {
+    let mut pattern = [0; 16];
+    let range = cpu.memory_range(cpu.i as usize, pattern.len())?;
+    pattern.copy_from_slice(&cpu.memory[range]);
+    cpu.pattern = Some(pattern);
+    cpu.audio.set_pattern(&pattern, cpu.pitch);
+    Ok(())
 }
 /*
 |  FX07 - LD VX, DT (Set VX to the value of the delay timer)
 |
 |  The value of DT is placed into VX.
 */
-pub fn set_vx_to_delay_timer(cpu: &mut CPU, x: u8) {
+pub fn set_vx_to_delay_timer(cpu: &mut CPU, x: u8) -> Result<(), Fault> {
     cpu.v[x as usize] = cpu.delay_timer;
+    Ok(())
 }
 /*
+|  !AMBIGUOUS!
 |  FX0A - LD VX, K (Wait for a key press, store the value of the key in VX)
 |
 |  All execution stops until a key is pressed, then the value of that key
-|  is stored in VX.
+|  is stored in VX. Only a key pressed during the wait counts, keys already
+|  held when it starts must be released first. The COSMAC VIP only continues
+|  once that key is released again.
 */
-pub fn wait_for_keypress(cpu: &mut CPU, x: u8) {
-    //
+pub fn wait_for_keypress(cpu: &mut CPU, x: u8) -> Result<(), Fault> {
+    if let Some(key) = cpu.waiting_key {
+        if !cpu.keys[key as usize] {
+            cpu.v[x as usize] = key;
+            cpu.waiting_key = None;
+            cpu.held_keys = None;
+            return Ok(());
+        }
+    } else {
+        let keys = cpu.keys;
+        let held = cpu.held_keys.get_or_insert(keys);
+
+        // A held key that is released can be pressed again
+        for (held, pressed) in held.iter_mut().zip(keys) {
+            *held &= pressed;
+        }
+        let pressed = (0..16).find(|&key| keys[key] && !held[key]);
+        if let Some(key) = pressed {
+            if !cpu.options.wait_for_key_release {
+                cpu.v[x as usize] = key as u8;
+                cpu.held_keys = None;
+                return Ok(());
+            }
+            cpu.waiting_key = Some(key as u8);
+        }
+    }
+
+    // Block by executing this instruction again on the next step
+    cpu.pc = cpu.pc.wrapping_sub(2);
+    Ok(())
 }
 /*
 |  FX15 - LD DT, VX (Set the delay timer to VX)
 |
 |  DT is set equal to the value of VX.
 */
-pub fn set_delay_timer_to_vx(cpu: &mut CPU, x: u8) {
+pub fn set_delay_timer_to_vx(cpu: &mut CPU, x: u8) -> Result<(), Fault> {
     cpu.delay_timer = cpu.v[x as usize];
+    Ok(())
 }
 /*
 |  FX18 - LD ST, VX (Set the sound timer to VX)
 |
 |  ST is set equal to the value of VX.
 */
-pub fn set_sound_timer_to_vx(cpu: &mut CPU, x: u8) {
+pub fn set_sound_timer_to_vx(cpu: &mut CPU, x: u8) -> Result<(), Fault> {
     cpu.sound_timer = cpu.v[x as usize];
+    Ok(())
 }
 /*
+|  !AMBIGUOUS!
 |  FX1E - ADD I, VX (Set I to the value of I plus the value of VX)
 |
-|  The values of I and VX are added, and the results are stored in I.
+|  The values of I and VX are added, and the results are stored in I. The
+|  Amiga interpreter also sets VF to 1 when I leaves the 12-bit address
+|  space, and to 0 otherwise.
 */
-pub fn add_vx_to_i(cpu: &mut CPU, x: u8) {
+pub fn add_vx_to_i(cpu: &mut CPU, x: u8) -> Result<(), Fault> {
     cpu.i = cpu.i.wrapping_add(cpu.v[x as usize] as u16);
+    if cpu.options.set_vf_on_i_overflow {
+        cpu.v[0xF] = if cpu.i > 0x0FFF { 1 } else { 0 };
+    }
+    Ok(())
 }
 /*
 |  FX29 - LD F, VX (Set I to the location of the sprite for the character in VX)
@@ -349,8 +624,22 @@ pub fn add_vx_to_i(cpu: &mut CPU, x: u8) {
 |  The value of I is set to the location for the hexadecimal sprite
 |  corresponding to the value of VX.
 */
-pub fn set_i_to_sprite_location(cpu: &mut CPU, x: u8) {
-    cpu.i = cpu.v[x as usize] as u16 * 5;
+pub fn set_i_to_sprite_location(cpu: &mut CPU, x: u8) -> Result<(), Fault> {
+    let glyph = (cpu.v[x as usize] & 0xF) as u16 * SMALL_GLYPH_SIZE;
+    cpu.i = cpu.options.font_address.wrapping_add(glyph);
+    Ok(())
+}
+/*
+|  FX30 - LD HF, VX (Set I to the location of the large sprite for the digit in VX) [SUPER-CHIP 1.1]
+|
+|  The value of I is set to the location for the 8x10 sprite corresponding
+|  to the value of VX. The large font follows the small font in memory.
+*/
+pub fn set_i_to_large_sprite_location(cpu: &mut CPU, x: u8) -> Result<(), Fault> {
+    let large_font_address = cpu.options.font_address.wrapping_add(16 * SMALL_GLYPH_SIZE);
+    let glyph = (cpu.v[x as usize] & 0xF) as u16 * LARGE_GLYPH_SIZE;
+    cpu.i = large_font_address.wrapping_add(glyph);
+    Ok(())
 }
 /*
 |  FX33 - LD B, VX (Store BCD representation of VX in memory locations I, I+1, and I+2)
@@ -359,10 +648,24 @@ pub fn set_i_to_sprite_location(cpu: &mut CPU, x: u8) {
 |  digit in memory at location in I, the tens digit at location I+1, and
 |  the ones digit at location I+2.
 */
-pub fn set_bcd_of_vx_at_i(cpu: &mut CPU, x: u8) {
-    cpu.memory[cpu.i as usize] = cpu.v[x as usize] / 100;
-    cpu.memory[cpu.i as usize + 1] = (cpu.v[x as usize] / 10) % 10;
-    cpu.memory[cpu.i as usize + 2] = cpu.v[x as usize] % 10;
+pub fn set_bcd_of_vx_at_i(cpu: &mut CPU, x: u8) -> Result<(), Fault> {
+    let vx = cpu.v[x as usize];
+    cpu.write(cpu.i as usize, vx / 100)?;
+    cpu.write(cpu.i as usize + 1, (vx / 10) % 10)?;
+    cpu.write(cpu.i as usize + 2, vx % 10)?;
+    Ok(())
+}
+/*
+|  FX3A - PITCH VX (Set the audio pattern pitch to VX) [XO-CHIP]
+|
+|  The pattern is played at 4000 * 2 ^ ((VX - 64) / 48) bits per second.
+*/
+pub fn set_pitch_to_vx(cpu: &mut CPU, x: u8) -> Result<(), Fault> {
+    cpu.pitch = cpu.v[x as usize];
+    if let Some(pattern) = cpu.pattern {
+        cpu.audio.set_pattern(&pattern, cpu.pitch);
+    }
+    Ok(())
 }
 /*
 |  !AMBIGUOUS!
@@ -371,13 +674,14 @@ pub fn set_bcd_of_vx_at_i(cpu: &mut CPU, x: u8) {
 |  The interpreter copies the values of registers V0 through VX into
 |  memory, starting at the address in I.
 */
-pub fn store_registers_in_memory(cpu: &mut CPU, x: u8) {
-    for i in 0..x + 1 {
-        cpu.memory[cpu.i as usize + i as usize] = cpu.v[i as usize];
-    }
+pub fn store_registers_in_memory(cpu: &mut CPU, x: u8) -> Result<(), Fault> {
+    let count = x as usize + 1;
+    let range = cpu.memory_range(cpu.i as usize, count)?;
+    cpu.memory[range].copy_from_slice(&cpu.v[..count]);
     if cpu.options.increment_i_when_storing_loading_memory {
         cpu.i = cpu.i.wrapping_add(x as u16 + 1);
     }
+    Ok(())
 }
 /*
 |  !AMBIGUOUS!
@@ -385,11 +689,426 @@ pub fn store_registers_in_memory(cpu: &mut CPU, x: u8) {
 |
 |  The interpreter reads values from memory starting at location I into registers V0 through VX.
 */
-pub fn load_registers_from_memory(cpu: &mut CPU, x: u8) {
-    for i in 0..x + 1 {
-        cpu.v[i as usize] = cpu.memory[cpu.i as usize + i as usize];
-    }
+pub fn load_registers_from_memory(cpu: &mut CPU, x: u8) -> Result<(), Fault> {
+    let count = x as usize + 1;
+    let range = cpu.memory_range(cpu.i as usize, count)?;
+    cpu.v[..count].copy_from_slice(&cpu.memory[range]);
     if cpu.options.increment_i_when_storing_loading_memory {
         cpu.i = cpu.i.wrapping_add(x as u16 + 1);
     }
+    Ok(())
+}
+/*
+|  FX75 - LD R, VX (Store registers V0 through VX in the RPL user flags) [SUPER-CHIP]
+|
+|  The interpreter copies the values of registers V0 through VX into the
+|  RPL user flags of the HP48. SUPER-CHIP has 8 flags, so it stops at V7,
+|  XO-CHIP has 16. The flags survive a restart, so they are saved to the
+|  flag storage.
+*/
+pub fn store_registers_in_rpl_flags(cpu: &mut CPU, x: u8) -> Result<(), Fault> {
+    let count = rpl_flag_count(cpu, x);
+    cpu.rpl[..count].copy_from_slice(&cpu.v[..count]);
+    cpu.flags.save(&cpu.rpl);
+    Ok(())
+}
+/*
+|  FX85 - LD VX, R (Read registers V0 through VX from the RPL user flags) [SUPER-CHIP]
+|
+|  The interpreter reads the RPL user flags into registers V0 through VX.
+*/
+pub fn load_registers_from_rpl_flags(cpu: &mut CPU, x: u8) -> Result<(), Fault> {
+    let count = rpl_flag_count(cpu, x);
+    cpu.v[..count].copy_from_slice(&cpu.rpl[..count]);
+    Ok(())
+}
+// Flags FX75/FX85 copy, V0 through VX but no more than the machine has
+fn rpl_flag_count(cpu: &CPU, x: u8) -> usize {
+    let flags = match cpu.options.instruction_set {
+        InstructionSet::XoChip => 16,
+        _ => 8,
+    };
+    (x as usize + 1).min(flags)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Options;
+    use crate::error::Chip8Error;
+    use crate::graphics::NullDisplay;
+    use crate::platform::Platform;
+    use crate::state;
+
+    fn machine(options: Options, rom: &[u8]) -> CPU {
+        let mut cpu = CPU::new(Box::new(NullDisplay::new()), options);
+        cpu.load_rom(rom).unwrap();
+        cpu
+    }
+
+    // FX0A into V3, then spin
+    const WAIT_FOR_KEY: [u8; 4] = [0xF3, 0x0A, 0x12, 0x02];
+
+    fn waiting(wait_for_key_release: bool) -> CPU {
+        let options = Options {
+            wait_for_key_release,
+            ..Options::default()
+        };
+        machine(options, &WAIT_FOR_KEY)
+    }
+
+    #[test]
+    fn fx0a_ignores_key_held_when_waiting_starts() {
+        let mut cpu = waiting(false);
+        cpu.keys[5] = true;
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.pc, 0x200);
+
+        // Only a press after letting go counts
+        cpu.keys[5] = false;
+        cpu.step().unwrap();
+        assert_eq!(cpu.pc, 0x200);
+        cpu.keys[5] = true;
+        cpu.step().unwrap();
+        assert_eq!(cpu.pc, 0x202);
+        assert_eq!(cpu.v[3], 5);
+    }
+
+    #[test]
+    fn fx0a_takes_another_key_while_one_is_held() {
+        let mut cpu = waiting(false);
+        cpu.keys[5] = true;
+        cpu.step().unwrap();
+        cpu.keys[7] = true;
+        cpu.step().unwrap();
+        assert_eq!(cpu.pc, 0x202);
+        assert_eq!(cpu.v[3], 7);
+    }
+
+    #[test]
+    fn fx0a_returns_key_on_release() {
+        let mut cpu = waiting(true);
+        cpu.step().unwrap();
+        cpu.keys[9] = true;
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.pc, 0x200);
+        assert_eq!(cpu.waiting_key, Some(9));
+        assert_eq!(cpu.v[3], 0);
+
+        cpu.keys[9] = false;
+        cpu.step().unwrap();
+        assert_eq!(cpu.pc, 0x202);
+        assert_eq!(cpu.v[3], 9);
+        assert_eq!(cpu.waiting_key, None);
+        assert_eq!(cpu.held_keys, None);
+    }
+
+    #[test]
+    fn fx0a_survives_save_and_load_mid_wait() {
+        // A held key and a pressed key, each restored into a fresh machine
+        let mut cpu = waiting(true);
+        cpu.keys[2] = true;
+        cpu.step().unwrap();
+        let held = state::save(&cpu);
+        cpu.keys[9] = true;
+        cpu.step().unwrap();
+        let pressed = state::save(&cpu);
+
+        let mut restored = waiting(true);
+        state::load(&mut restored, &held).unwrap();
+        restored.step().unwrap();
+        assert_eq!(restored.pc, 0x200);
+        restored.keys[2] = false;
+        restored.step().unwrap();
+        restored.keys[2] = true;
+        restored.step().unwrap();
+        restored.keys[2] = false;
+        restored.step().unwrap();
+        assert_eq!((restored.pc, restored.v[3]), (0x202, 2));
+
+        let mut restored = waiting(true);
+        state::load(&mut restored, &pressed).unwrap();
+        assert_eq!(restored.waiting_key, Some(9));
+        restored.keys = [false; 16];
+        restored.step().unwrap();
+        assert_eq!((restored.pc, restored.v[3]), (0x202, 9));
+    }
+
+    fn run(cpu: &mut CPU, steps: usize) {
+        for _ in 0..steps {
+            cpu.step().unwrap();
+        }
+    }
+
+    fn chip48(rom: &[u8]) -> CPU {
+        machine(Platform::Chip48.options(), rom)
+    }
+
+    #[test]
+    fn shifts_set_vf_to_the_shifted_out_bit() {
+        // V1 = 0x81, SHR V1 then SHL V2 with V2 = 0x40
+        let mut cpu = chip48(&[0x61, 0x81, 0x62, 0x40, 0x81, 0x16, 0x82, 0x2E]);
+        run(&mut cpu, 3);
+        assert_eq!((cpu.v[1], cpu.v[0xF]), (0x40, 1));
+        cpu.step().unwrap();
+        assert_eq!((cpu.v[2], cpu.v[0xF]), (0x80, 0));
+    }
+
+    #[test]
+    fn shifts_of_vf_keep_the_flag() {
+        // VF = 0x01 SHR VF, then VF = 0x80 SHL VF
+        let mut cpu = chip48(&[0x6F, 0x01, 0x8F, 0xF6, 0x6F, 0x80, 0x8F, 0xFE]);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.v[0xF], 1);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.v[0xF], 1);
+    }
+
+    #[test]
+    fn shifts_take_vy_with_the_shift_quirk() {
+        let options = Options {
+            put_value_of_vy_into_vx_before_shifting: true,
+            ..Options::default()
+        };
+        // V2 = 0x03, SHR V1, V2
+        let mut cpu = machine(options, &[0x62, 0x03, 0x81, 0x26]);
+        run(&mut cpu, 2);
+        assert_eq!((cpu.v[1], cpu.v[0xF]), (0x01, 1));
+    }
+
+    #[test]
+    fn logic_ops_reset_vf_with_the_quirk() {
+        for (operation, expected) in [(0x21, 0x0F), (0x22, 0x0C), (0x23, 0x03)] {
+            // V1 = 0x0C, V2 = 0x0F, VF = 5, then OR, AND or XOR V1, V2
+            let rom = [0x61, 0x0C, 0x62, 0x0F, 0x6F, 0x05, 0x81, operation];
+            for reset_vf_on_logic_ops in [false, true] {
+                let options = Options {
+                    reset_vf_on_logic_ops,
+                    ..Options::default()
+                };
+                let mut cpu = machine(options, &rom);
+                run(&mut cpu, 4);
+                assert_eq!(cpu.v[1], expected);
+                assert_eq!(cpu.v[0xF], if reset_vf_on_logic_ops { 0 } else { 5 });
+            }
+        }
+    }
+
+    // A 4x4 block drawn at (V0, V1) with DXY4, 4 pixels from the right and
+    // 2 rows from the bottom edge
+    fn draw_block(clip_sprites: bool, hires: bool) -> CPU {
+        let options = Options {
+            clip_sprites,
+            ..Options::default()
+        };
+        let mut cpu = machine(options, &[]);
+        cpu.pixels.set_hires(hires);
+        cpu.memory[0x300..0x304].copy_from_slice(&[0xF0; 4]);
+        cpu.i = 0x300;
+        cpu.v[0] = cpu.pixels.width() as u8 - 2;
+        cpu.v[1] = cpu.pixels.height() as u8 - 2;
+        draw_sprite(&mut cpu, 0, 1, 4).unwrap();
+        cpu
+    }
+
+    fn lit(cpu: &CPU) -> usize {
+        cpu.pixels
+            .rows()
+            .flatten()
+            .filter(|&&color| color != 0)
+            .count()
+    }
+
+    #[test]
+    fn draw_clips_at_the_right_and_bottom_edges() {
+        for hires in [false, true] {
+            let cpu = draw_block(true, hires);
+            let (width, height) = (cpu.pixels.width(), cpu.pixels.height());
+            assert_eq!(lit(&cpu), 4);
+            assert_eq!(cpu.pixels.get(width - 1, height - 1), FIRST_PLANE);
+            assert_eq!(cpu.pixels.get(0, 0), 0);
+            assert_eq!(cpu.v[0xF], 0);
+        }
+    }
+
+    #[test]
+    fn draw_wraps_at_the_right_and_bottom_edges() {
+        for hires in [false, true] {
+            let cpu = draw_block(false, hires);
+            let (width, height) = (cpu.pixels.width(), cpu.pixels.height());
+            assert_eq!(lit(&cpu), 16);
+            for (x, y) in [
+                (width - 2, height - 2),
+                (1, height - 1),
+                (width - 1, 1),
+                (0, 0),
+            ] {
+                assert_eq!(cpu.pixels.get(x, y), FIRST_PLANE, "{} {}", x, y);
+            }
+            assert_eq!(cpu.pixels.get(2, 2), 0);
+            assert_eq!(cpu.v[0xF], 0);
+        }
+    }
+
+    #[test]
+    fn draw_wraps_the_start_coordinates() {
+        let options = Options {
+            clip_sprites: true,
+            ..Options::default()
+        };
+        let mut cpu = machine(options, &[]);
+        cpu.memory[0x300] = 0x80;
+        cpu.i = 0x300;
+        cpu.v[0] = 64 + 3;
+        cpu.v[1] = 32 + 5;
+        draw_sprite(&mut cpu, 0, 1, 1).unwrap();
+        assert_eq!(cpu.pixels.get(3, 5), FIRST_PLANE);
+    }
+
+    #[test]
+    fn draw_sets_vf_on_collision_with_wrapped_pixels_only() {
+        for clip_sprites in [false, true] {
+            let options = Options {
+                clip_sprites,
+                ..Options::default()
+            };
+            let mut cpu = machine(options, &[]);
+            cpu.pixels.set(0, 0, FIRST_PLANE);
+            cpu.memory[0x300..0x304].copy_from_slice(&[0xF0; 4]);
+            cpu.i = 0x300;
+            cpu.v[0] = 62;
+            cpu.v[1] = 30;
+            draw_sprite(&mut cpu, 0, 1, 4).unwrap();
+            assert_eq!(cpu.v[0xF], !clip_sprites as u8);
+            assert_eq!(cpu.pixels.get(0, 0), clip_sprites as u8);
+        }
+    }
+
+    #[test]
+    fn draw_sets_vf_when_erasing() {
+        let mut cpu = draw_block(true, false);
+        cpu.v[0xF] = 0;
+        draw_sprite(&mut cpu, 0, 1, 4).unwrap();
+        assert_eq!(cpu.v[0xF], 1);
+        assert_eq!(lit(&cpu), 0);
+    }
+
+    #[test]
+    fn hires_draw_counts_collided_and_clipped_rows() {
+        let options = Options {
+            clip_sprites: true,
+            count_collided_rows: true,
+            ..Options::default()
+        };
+        let mut cpu = machine(options, &[]);
+        cpu.pixels.set_hires(true);
+        cpu.pixels.set(126, 62, FIRST_PLANE);
+        cpu.memory[0x300..0x304].copy_from_slice(&[0xF0; 4]);
+        cpu.i = 0x300;
+        cpu.v[0] = 126;
+        cpu.v[1] = 62;
+        draw_sprite(&mut cpu, 0, 1, 4).unwrap();
+        // One row collided and two were clipped
+        assert_eq!(cpu.v[0xF], 3);
+
+        // Low resolution only reports whether anything collided
+        cpu.pixels.set_hires(false);
+        cpu.v[0] = 62;
+        cpu.v[1] = 30;
+        draw_sprite(&mut cpu, 0, 1, 4).unwrap();
+        draw_sprite(&mut cpu, 0, 1, 4).unwrap();
+        assert_eq!(cpu.v[0xF], 1);
+    }
+
+    #[test]
+    fn draw_waits_for_the_next_frame_with_the_quirk() {
+        // I = 0x20A, three 1-row draws, then spin
+        let rom = [
+            0xA2, 0x0A, 0xD0, 0x11, 0xD0, 0x11, 0xD0, 0x11, 0x12, 0x08, 0x80,
+        ];
+        let options = Options {
+            wait_for_vblank: true,
+            ..Options::default()
+        };
+        let mut cpu = machine(options, &rom);
+        cpu.run_frame().unwrap();
+        assert_eq!((cpu.pc, cpu.frames), (0x204, 1));
+        cpu.run_frame().unwrap();
+        assert_eq!((cpu.pc, cpu.frames), (0x206, 2));
+        assert!(!cpu.waiting_for_vblank);
+        assert_eq!(cpu.frame_cycle, 0);
+
+        let mut cpu = machine(Options::default(), &rom);
+        cpu.run_frame().unwrap();
+        assert_eq!((cpu.pc, cpu.frames), (0x208, 1));
+    }
+
+    #[test]
+    fn call_overflows_at_the_configured_depth() {
+        for stack_address in [None, Some(0xECF)] {
+            let options = Options {
+                stack_depth: 12,
+                stack_address,
+                ..Options::default()
+            };
+            // Call itself forever
+            let mut cpu = machine(options, &[0x22, 0x00]);
+            run(&mut cpu, 12);
+            assert_eq!(cpu.sp, 12);
+            assert_eq!(
+                cpu.step(),
+                Err(Chip8Error::StackOverflow {
+                    pc: 0x200,
+                    opcode: 0x2200
+                })
+            );
+            assert_eq!(cpu.sp, 12);
+        }
+    }
+
+    #[test]
+    fn return_underflows_an_empty_stack() {
+        let mut cpu = machine(Options::default(), &[0x22, 0x04, 0x00, 0xEE, 0x00, 0xEE]);
+        run(&mut cpu, 2);
+        assert_eq!((cpu.pc, cpu.sp), (0x202, 0));
+        assert_eq!(
+            cpu.step(),
+            Err(Chip8Error::StackUnderflow {
+                pc: 0x202,
+                opcode: 0x00EE
+            })
+        );
+    }
+
+    #[test]
+    fn stack_depth_must_fit_the_stack() {
+        for (stack_depth, valid) in [(0, false), (1, true), (16, true), (17, false)] {
+            let options = Options {
+                stack_depth,
+                ..Options::default()
+            };
+            assert_eq!(options.validate().is_ok(), valid, "{}", stack_depth);
+        }
+    }
+
+    #[test]
+    fn vip_stack_grows_down_from_0xecf() {
+        // Two nested calls, then spin
+        let mut cpu = machine(
+            Platform::CosmacVip.options(),
+            &[0x22, 0x04, 0x00, 0x00, 0x22, 0x08, 0x00, 0x00, 0x12, 0x08],
+        );
+        run(&mut cpu, 2);
+        assert_eq!(cpu.memory[0xECA..0xED0], [0, 0, 0x02, 0x06, 0x02, 0x02]);
+        assert_eq!(cpu.memory[0xED0], 0);
+        assert_eq!(cpu.stack_entry(0), Ok(0x202));
+        assert_eq!(cpu.stack_entry(1), Ok(0x206));
+
+        // A ROM that changes the stack returns where it says
+        cpu.memory[0xECD] = 0x10;
+        assert_eq!(cpu.pop(), Ok(0x210));
+        assert_eq!(cpu.pop(), Ok(0x202));
+    }
 }
@@ -0,0 +1,142 @@
+use crate::cpu::Options;
+use crate::font::FontSet;
+use std::str::FromStr;
+
+// Instruction sets, each a superset of the one before
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum InstructionSet {
+    Chip8,
+    SuperChip10, // SUPER-CHIP 1.0, without scrolling and the large font
+    SuperChip,
+    XoChip,
+}
+
+impl InstructionSet {
+    // Bytes of addressable memory
+    pub fn memory_size(self) -> usize {
+        match self {
+            InstructionSet::Chip8 | InstructionSet::SuperChip10 | InstructionSet::SuperChip => {
+                0x1000
+            }
+            InstructionSet::XoChip => 0x10000,
+        }
+    }
+}
+
+// Historical interpreters, each with its own set of quirks
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Platform {
+    CosmacVip,
+    Chip48,
+    SuperChip10,
+    SuperChip11,
+    #[default]
+    ModernSuperChip,
+    XoChip,
+}
+
+impl Platform {
+    pub const ALL: [Platform; 6] = [
+        Platform::CosmacVip,
+        Platform::Chip48,
+        Platform::SuperChip10,
+        Platform::SuperChip11,
+        Platform::ModernSuperChip,
+        Platform::XoChip,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Platform::CosmacVip => "vip",
+            Platform::Chip48 => "chip48",
+            Platform::SuperChip10 => "schip10",
+            Platform::SuperChip11 => "schip11",
+            Platform::ModernSuperChip => "schip",
+            Platform::XoChip => "xochip",
+        }
+    }
+
+    // The complete quirk set of the platform
+    pub fn options(self) -> Options {
+        let vip = Options {
+            put_value_of_vy_into_vx_before_shifting: true,
+            jump_to_nnn_plus_the_value_in_v0: true,
+            increment_i_when_storing_loading_memory: true,
+            wait_for_key_release: true,
+            set_vf_on_i_overflow: false,
+            reset_vf_on_logic_ops: true,
+            clip_sprites: true,
+            wait_for_vblank: true,
+            stack_depth: 12,
+            stack_address: Some(0xECF),
+            font: FontSet::CosmacVip,
+            font_address: 0x050,
+            instruction_set: InstructionSet::Chip8,
+            count_collided_rows: false,
+        };
+        let chip48 = Options {
+            put_value_of_vy_into_vx_before_shifting: false,
+            jump_to_nnn_plus_the_value_in_v0: false,
+            increment_i_when_storing_loading_memory: false,
+            wait_for_key_release: false,
+            set_vf_on_i_overflow: false,
+            reset_vf_on_logic_ops: false,
+            clip_sprites: true,
+            wait_for_vblank: false,
+            stack_depth: 16,
+            stack_address: None,
+            font: FontSet::Chip48,
+            font_address: 0x050,
+            instruction_set: InstructionSet::Chip8,
+            count_collided_rows: false,
+        };
+        let schip = Options {
+            instruction_set: InstructionSet::SuperChip,
+            count_collided_rows: true,
+            ..chip48
+        };
+
+        match self {
+            Platform::CosmacVip => vip,
+            Platform::XoChip => Options {
+                reset_vf_on_logic_ops: false,
+                clip_sprites: false,
+                wait_for_vblank: false,
+                stack_depth: 16,
+                stack_address: None,
+                font: FontSet::Octo,
+                instruction_set: InstructionSet::XoChip,
+                ..vip
+            },
+            Platform::Chip48 => chip48,
+            Platform::SuperChip10 => Options {
+                instruction_set: InstructionSet::SuperChip10,
+                ..schip
+            },
+            Platform::SuperChip11 => schip,
+            Platform::ModernSuperChip => Options {
+                count_collided_rows: false,
+                ..schip
+            },
+        }
+    }
+}
+
+impl FromStr for Platform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Platform, String> {
+        Platform::ALL
+            .iter()
+            .copied()
+            .find(|platform| platform.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let names: Vec<&str> = Platform::ALL.iter().map(|p| p.name()).collect();
+                format!(
+                    "Unknown platform: {} (expected one of {})",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
@@ -0,0 +1,226 @@
+use crate::chip8::Chip8;
+use std::collections::VecDeque;
+use std::mem;
+
+// Bytes of unchanged state that end a run of changes in a delta
+const MAX_RUN_GAP: usize = 8;
+
+// The changes that turn one save state into the one recorded before it,
+// kept as runs of (offset, older bytes)
+struct Delta {
+    runs: Vec<(u32, Vec<u8>)>,
+    size: usize,
+}
+
+impl Delta {
+    fn between(newer: &[u8], older: &[u8]) -> Delta {
+        let mut runs: Vec<(u32, Vec<u8>)> = Vec::new();
+        let mut size = 0;
+        let mut offset = 0;
+        while offset < older.len() {
+            if newer[offset] == older[offset] {
+                offset += 1;
+                continue;
+            }
+
+            // Extend the run until MAX_RUN_GAP bytes in a row are unchanged
+            let start = offset;
+            let mut end = offset + 1;
+            while end < older.len() && end - offset <= MAX_RUN_GAP {
+                if newer[end] != older[end] {
+                    offset = end;
+                }
+                end += 1;
+            }
+            offset += 1;
+            runs.push((start as u32, older[start..offset].to_vec()));
+            size += offset - start + 4;
+        }
+        Delta { runs, size }
+    }
+
+    fn apply(&self, state: &mut [u8]) {
+        for (offset, bytes) in &self.runs {
+            let offset = *offset as usize;
+            state[offset..offset + bytes.len()].copy_from_slice(bytes);
+        }
+    }
+}
+
+// Ring buffer of the last frames of a running machine, stored as deltas
+// between consecutive save states
+pub struct RewindBuffer {
+    max_frames: usize,       // Frames kept before the oldest is dropped
+    max_bytes: usize,        // Memory the deltas may use before the oldest is dropped
+    latest: Vec<u8>,         // Save state of the most recently recorded frame
+    scratch: Vec<u8>,        // Save state being recorded, kept for its allocation
+    deltas: VecDeque<Delta>, // Oldest first, each leads one frame back
+    bytes: usize,            // Memory used by the deltas
+}
+
+impl RewindBuffer {
+    pub fn new(max_frames: usize, max_bytes: usize) -> RewindBuffer {
+        RewindBuffer {
+            max_frames,
+            max_bytes,
+            latest: Vec::new(),
+            scratch: Vec::new(),
+            deltas: VecDeque::new(),
+            bytes: 0,
+        }
+    }
+
+    // Number of frames that can be rewound
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    // Memory used by the recorded frames, in bytes
+    pub fn memory_used(&self) -> usize {
+        self.latest.len() + self.bytes
+    }
+
+    pub fn clear(&mut self) {
+        self.latest.clear();
+        self.deltas.clear();
+        self.bytes = 0;
+    }
+
+    // Records the current frame of `chip8`, call once after every frame
+    pub fn record(&mut self, chip8: &Chip8) {
+        // Without frames to keep there is nothing to serialize
+        if self.max_frames == 0 {
+            return;
+        }
+        chip8.save_state_into(&mut self.scratch);
+        if self.scratch.len() != self.latest.len() {
+            // The machine changed shape, the older frames no longer fit
+            self.clear();
+        } else {
+            let delta = Delta::between(&self.scratch, &self.latest);
+            self.bytes += delta.size;
+            self.deltas.push_back(delta);
+            while self.deltas.len() > self.max_frames || self.bytes > self.max_bytes {
+                match self.deltas.pop_front() {
+                    Some(oldest) => self.bytes -= oldest.size,
+                    None => break,
+                }
+            }
+        }
+        mem::swap(&mut self.latest, &mut self.scratch);
+    }
+
+    // Moves `chip8` one recorded frame back, returns false when there are
+    // no older frames left
+    pub fn rewind(&mut self, chip8: &mut Chip8) -> bool {
+        let delta = match self.deltas.pop_back() {
+            Some(delta) => delta,
+            None => return false,
+        };
+        self.bytes -= delta.size;
+        delta.apply(&mut self.latest);
+        if chip8.load_state(&self.latest).is_err() {
+            // Another ROM was loaded, none of the frames apply to it
+            self.clear();
+            return false;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Options;
+
+    // Counts in V0 and writes its digits to memory every instruction
+    const ROM: [u8; 8] = [0x70, 0x01, 0xA3, 0x00, 0xF0, 0x33, 0x12, 0x00];
+
+    // Records the first frame and `frames` more, returns the state hash of
+    // each of them
+    fn play(rewind: &mut RewindBuffer, chip8: &mut Chip8, frames: usize) -> Vec<u64> {
+        let mut hashes = vec![chip8.state_hash()];
+        rewind.record(chip8);
+        for _ in 0..frames {
+            chip8.run_frame().unwrap();
+            rewind.record(chip8);
+            hashes.push(chip8.state_hash());
+        }
+        hashes
+    }
+
+    fn machine() -> Chip8 {
+        let mut chip8 = Chip8::headless(Options::default()).unwrap();
+        chip8.load_rom(&ROM).unwrap();
+        chip8
+    }
+
+    #[test]
+    fn rewinds_to_recorded_frames() {
+        let mut chip8 = machine();
+        let mut rewind = RewindBuffer::new(100, usize::MAX);
+        let hashes = play(&mut rewind, &mut chip8, 20);
+        assert_eq!(rewind.len(), 20);
+
+        for _ in 0..7 {
+            assert!(rewind.rewind(&mut chip8));
+        }
+        assert_eq!(chip8.state_hash(), hashes[20 - 7]);
+
+        // Recording again continues from the frame rewound to
+        chip8.run_frame().unwrap();
+        rewind.record(&chip8);
+        assert_eq!(chip8.state_hash(), hashes[20 - 6]);
+        while rewind.rewind(&mut chip8) {}
+        assert_eq!(chip8.state_hash(), hashes[0]);
+        assert!(rewind.is_empty());
+    }
+
+    #[test]
+    fn drops_the_oldest_frames_beyond_the_frame_limit() {
+        let mut chip8 = machine();
+        let mut rewind = RewindBuffer::new(5, usize::MAX);
+        let hashes = play(&mut rewind, &mut chip8, 12);
+        assert_eq!(rewind.len(), 5);
+
+        for _ in 0..5 {
+            assert!(rewind.rewind(&mut chip8));
+        }
+        assert_eq!(chip8.state_hash(), hashes[12 - 5]);
+        assert!(!rewind.rewind(&mut chip8));
+        assert_eq!(chip8.state_hash(), hashes[12 - 5]);
+    }
+
+    #[test]
+    fn drops_the_oldest_frames_beyond_the_memory_limit() {
+        let mut chip8 = machine();
+        let mut unlimited = RewindBuffer::new(100, usize::MAX);
+        play(&mut unlimited, &mut chip8, 12);
+        let state_size = chip8.save_state().len();
+        let max_bytes = (unlimited.memory_used() - state_size) / 3;
+
+        let mut chip8 = machine();
+        let mut rewind = RewindBuffer::new(100, max_bytes);
+        let hashes = play(&mut rewind, &mut chip8, 12);
+        let kept = rewind.len();
+        assert!(kept > 0 && kept < 12);
+        assert!(rewind.memory_used() - state_size <= max_bytes);
+
+        while rewind.rewind(&mut chip8) {}
+        assert_eq!(chip8.state_hash(), hashes[12 - kept]);
+    }
+
+    #[test]
+    fn records_nothing_without_frames_to_keep() {
+        let mut chip8 = machine();
+        let mut rewind = RewindBuffer::new(0, usize::MAX);
+        play(&mut rewind, &mut chip8, 3);
+        assert!(rewind.is_empty());
+        assert_eq!(rewind.memory_used(), 0);
+        assert!(!rewind.rewind(&mut chip8));
+    }
+}
@@ -0,0 +1,54 @@
+// Random number source of CXNN, seeded so that runs can be reproduced
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Rng {
+    pub(crate) state: u64, // xorshift64* state, never zero
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // Spread the seed with SplitMix64 so that small seeds differ a lot
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        Rng { state: z.max(1) }
+    }
+
+    pub fn next_byte(&mut self) -> u8 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_gives_same_bytes() {
+        let (mut a, mut b) = (Rng::new(42), Rng::new(42));
+        for _ in 0..64 {
+            assert_eq!(a.next_byte(), b.next_byte());
+        }
+        assert_ne!(Rng::new(1), Rng::new(2));
+    }
+
+    #[test]
+    fn state_is_never_zero() {
+        for seed in [0, 1, u64::MAX] {
+            assert_ne!(Rng::new(seed).state, 0);
+        }
+    }
+
+    #[test]
+    fn bytes_cover_the_whole_range() {
+        let mut rng = Rng::new(7);
+        let mut seen = [false; 256];
+        for _ in 0..4096 {
+            seen[rng.next_byte() as usize] = true;
+        }
+        assert!(seen.iter().all(|&seen| seen));
+    }
+}
@@ -0,0 +1,378 @@
+use crate::cpu::{Options, CPU};
+use crate::error::StateError;
+use crate::font::FontSet;
+use crate::graphics::{NullDisplay, HIRES_HEIGHT, HIRES_WIDTH};
+use crate::platform::InstructionSet;
+use std::mem;
+
+/*
+|  Save state format, all numbers little-endian
+|
+|  magic "C8ST", version (u8), ROM hash (u64), options, then the machine:
+|  memory (u32 length + bytes), pc, i, v, stack depth + entries, timers,
+|  keypad, FX0A key and held keys, framebuffer (hires + 128 x 64 colors), XO-CHIP planes,
+|  pattern and pitch, RPL flags, halted, the position in the frame and the
+|  random number generator.
+*/
+const MAGIC: &[u8; 4] = b"C8ST";
+pub const STATE_VERSION: u8 = 5;
+
+// Serializes the whole machine, the video and audio sinks are not included
+pub(crate) fn save(cpu: &CPU) -> Vec<u8> {
+    let mut state = Vec::new();
+    save_into(cpu, &mut state);
+    state
+}
+
+// Serializes the whole machine into `state`, reusing its allocation
+pub(crate) fn save_into(cpu: &CPU, state: &mut Vec<u8>) {
+    state.clear();
+    state.reserve(cpu.memory.len() + 0x2100);
+    let mut w = Writer(mem::take(state));
+    w.bytes(MAGIC);
+    w.u8(STATE_VERSION);
+    w.u64(cpu.rom_hash);
+    write_options(&mut w, &cpu.options);
+
+    w.u32(cpu.memory.len() as u32);
+    w.bytes(&cpu.memory);
+    w.u16(cpu.pc);
+    w.u16(cpu.i);
+    w.bytes(&cpu.v);
+    w.u8(cpu.sp);
+    for entry in cpu.stack {
+        w.u16(entry);
+    }
+    w.u8(cpu.delay_timer);
+    w.u8(cpu.sound_timer);
+    for key in cpu.keys {
+        w.bool(key);
+    }
+    w.u8(cpu.waiting_key.map_or(0xFF, |key| key));
+    w.bool(cpu.held_keys.is_some());
+    for key in cpu.held_keys.unwrap_or_default() {
+        w.bool(key);
+    }
+    w.bool(cpu.pixels.is_hires());
+    for y in 0..HIRES_HEIGHT {
+        for x in 0..HIRES_WIDTH {
+            w.u8(cpu.pixels.get(x, y));
+        }
+    }
+    w.u8(cpu.planes);
+    w.bool(cpu.pattern.is_some());
+    w.bytes(&cpu.pattern.unwrap_or_default());
+    w.u8(cpu.pitch);
+    w.bytes(&cpu.rpl);
+    w.bool(cpu.halted);
+    w.u32(cpu.cycles_per_frame);
+    w.u32(cpu.frame_cycle);
+    w.bool(cpu.waiting_for_vblank);
+    w.u64(cpu.frames);
+    w.u64(cpu.rng.state);
+    *state = w.0;
+}
+
+// Replaces the machine with a saved one, leaving it untouched on error
+pub(crate) fn load(cpu: &mut CPU, state: &[u8]) -> Result<(), StateError> {
+    let mut r = Reader(state);
+    if r.bytes(MAGIC.len())? != MAGIC {
+        return Err(StateError::InvalidFormat);
+    }
+    let version = r.u8()?;
+    if version != STATE_VERSION {
+        return Err(StateError::UnsupportedVersion(version));
+    }
+    if r.u64()? != cpu.rom_hash {
+        return Err(StateError::RomMismatch);
+    }
+    let options = read_options(&mut r)?;
+
+    // Restore into a fresh machine, it only takes over the sinks on success
+    let mut saved = CPU::new(Box::new(NullDisplay::new()), options);
+    saved.rom_hash = cpu.rom_hash;
+    let size = r.u32()? as usize;
+    if size != saved.memory.len() {
+        return Err(StateError::InvalidFormat);
+    }
+    saved.memory.copy_from_slice(r.bytes(size)?);
+    saved.pc = r.u16()?;
+    saved.i = r.u16()?;
+    saved.v.copy_from_slice(r.bytes(16)?);
+    saved.sp = r.u8()?;
+    if saved.sp > options.stack_depth {
+        return Err(StateError::InvalidFormat);
+    }
+    for entry in saved.stack.iter_mut() {
+        *entry = r.u16()?;
+    }
+    saved.delay_timer = r.u8()?;
+    saved.sound_timer = r.u8()?;
+    for key in saved.keys.iter_mut() {
+        *key = r.bool()?;
+    }
+    saved.waiting_key = Some(r.u8()?).filter(|&key| key <= 0xF);
+    let waiting = r.bool()?;
+    let mut held = [false; 16];
+    for key in held.iter_mut() {
+        *key = r.bool()?;
+    }
+    saved.held_keys = Some(held).filter(|_| waiting);
+    saved.pixels.set_hires(r.bool()?);
+    for y in 0..HIRES_HEIGHT {
+        for x in 0..HIRES_WIDTH {
+            saved.pixels.set(x, y, r.u8()?);
+        }
+    }
+    saved.planes = r.u8()?;
+    let has_pattern = r.bool()?;
+    let mut pattern = [0; 16];
+    pattern.copy_from_slice(r.bytes(16)?);
+    saved.pattern = Some(pattern).filter(|_| has_pattern);
+    saved.pitch = r.u8()?;
+    saved.rpl.copy_from_slice(r.bytes(16)?);
+    saved.halted = r.bool()?;
+    saved.cycles_per_frame = r.u32()?.max(1);
+    saved.frame_cycle = r.u32()?;
+    saved.waiting_for_vblank = r.bool()?;
+    saved.frames = r.u64()?;
+    saved.rng.state = r.u64()?.max(1);
+
+    // The restored machine keeps playing on the same sinks
+    mem::swap(&mut saved.display, &mut cpu.display);
+    mem::swap(&mut saved.audio, &mut cpu.audio);
+    mem::swap(&mut saved.flags, &mut cpu.flags);
+    if let Some(pattern) = saved.pattern {
+        saved.audio.set_pattern(&pattern, saved.pitch);
+    }
+    saved.display.draw(&saved.pixels);
+    *cpu = saved;
+    Ok(())
+}
+
+pub(crate) fn write_options(w: &mut Writer, options: &Options) {
+    w.bool(options.put_value_of_vy_into_vx_before_shifting);
+    w.bool(options.jump_to_nnn_plus_the_value_in_v0);
+    w.bool(options.increment_i_when_storing_loading_memory);
+    w.bool(options.wait_for_key_release);
+    w.bool(options.set_vf_on_i_overflow);
+    w.bool(options.reset_vf_on_logic_ops);
+    w.bool(options.clip_sprites);
+    w.bool(options.wait_for_vblank);
+    w.u8(options.stack_depth);
+    w.bool(options.stack_address.is_some());
+    w.u16(options.stack_address.unwrap_or(0));
+    w.u8(FontSet::ALL
+        .iter()
+        .position(|&font| font == options.font)
+        .unwrap_or(0) as u8);
+    w.u16(options.font_address);
+    w.u8(match options.instruction_set {
+        InstructionSet::Chip8 => 0,
+        InstructionSet::SuperChip => 1,
+        InstructionSet::XoChip => 2,
+        InstructionSet::SuperChip10 => 3,
+    });
+    w.bool(options.count_collided_rows);
+}
+
+pub(crate) fn read_options(r: &mut Reader) -> Result<Options, StateError> {
+    let options = Options {
+        put_value_of_vy_into_vx_before_shifting: r.bool()?,
+        jump_to_nnn_plus_the_value_in_v0: r.bool()?,
+        increment_i_when_storing_loading_memory: r.bool()?,
+        wait_for_key_release: r.bool()?,
+        set_vf_on_i_overflow: r.bool()?,
+        reset_vf_on_logic_ops: r.bool()?,
+        clip_sprites: r.bool()?,
+        wait_for_vblank: r.bool()?,
+        stack_depth: r.u8()?,
+        stack_address: {
+            let in_memory = r.bool()?;
+            Some(r.u16()?).filter(|_| in_memory)
+        },
+        font: *FontSet::ALL
+            .get(r.u8()? as usize)
+            .ok_or(StateError::InvalidFormat)?,
+        font_address: r.u16()?,
+        instruction_set: match r.u8()? {
+            0 => InstructionSet::Chip8,
+            1 => InstructionSet::SuperChip,
+            2 => InstructionSet::XoChip,
+            3 => InstructionSet::SuperChip10,
+            _ => return Err(StateError::InvalidFormat),
+        },
+        count_collided_rows: r.bool()?,
+    };
+    options.validate().map_err(|_| StateError::InvalidFormat)?;
+    Ok(options)
+}
+
+pub(crate) struct Writer(pub(crate) Vec<u8>);
+
+impl Writer {
+    pub(crate) fn bytes(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes);
+    }
+
+    pub(crate) fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub(crate) fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    pub(crate) fn u16(&mut self, value: u16) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub(crate) fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub(crate) fn u64(&mut self, value: u64) {
+        self.bytes(&value.to_le_bytes());
+    }
+}
+
+pub(crate) struct Reader<'a>(pub(crate) &'a [u8]);
+
+impl<'a> Reader<'a> {
+    pub(crate) fn bytes(&mut self, count: usize) -> Result<&'a [u8], StateError> {
+        if self.0.len() < count {
+            return Err(StateError::InvalidFormat);
+        }
+        let (bytes, rest) = self.0.split_at(count);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    pub(crate) fn bool(&mut self) -> Result<bool, StateError> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, StateError> {
+        let mut bytes = [0; 2];
+        bytes.copy_from_slice(self.bytes(2)?);
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, StateError> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.bytes(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, StateError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Draws a glyph, rolls a random number, sets the delay timer and stays
+    // in a subroutine
+    const ROM: [u8; 18] = [
+        0x60, 0x0A, 0xA0, 0x50, 0xD0, 0x05, 0xC1, 0xFF, 0xF0, 0x15, 0x22, 0x10, 0x12, 0x0C, 0x00,
+        0x00, 0x12, 0x10,
+    ];
+
+    fn machine(options: Options) -> CPU {
+        let mut cpu = CPU::new(Box::new(NullDisplay::new()), options);
+        cpu.load_rom(&ROM).unwrap();
+        cpu
+    }
+
+    fn running() -> CPU {
+        let mut cpu = machine(Options::default());
+        cpu.run_frame().unwrap();
+        cpu.keys[3] = true;
+        cpu
+    }
+
+    #[test]
+    fn round_trips_the_machine() {
+        let cpu = running();
+        let state = save(&cpu);
+
+        let mut restored = machine(Options::default());
+        load(&mut restored, &state).unwrap();
+        assert_eq!(save(&restored), state);
+        assert_eq!(restored.pc, cpu.pc);
+        assert_eq!(restored.sp, 1);
+        assert_eq!(restored.v, cpu.v);
+        assert_eq!(restored.delay_timer, cpu.delay_timer);
+        assert_eq!(restored.keys, cpu.keys);
+        assert_eq!(restored.rng, cpu.rng);
+        assert_eq!(restored.pixels.get(10, 10), cpu.pixels.get(10, 10));
+    }
+
+    #[test]
+    fn round_trips_options() {
+        let options = Options {
+            instruction_set: InstructionSet::SuperChip10,
+            stack_depth: 16,
+            count_collided_rows: true,
+            ..Options::default()
+        };
+        let state = save(&machine(options));
+
+        let mut restored = machine(Options::default());
+        load(&mut restored, &state).unwrap();
+        assert_eq!(
+            restored.options.instruction_set,
+            InstructionSet::SuperChip10
+        );
+        assert_eq!(restored.options.stack_depth, 16);
+        assert!(restored.options.count_collided_rows);
+    }
+
+    #[test]
+    fn save_into_matches_save() {
+        let cpu = running();
+        let mut state = vec![0xAA; 8];
+        save_into(&cpu, &mut state);
+        assert_eq!(state, save(&cpu));
+    }
+
+    #[test]
+    fn rejects_invalid_states() {
+        let state = save(&running());
+        let mut cpu = machine(Options::default());
+        let before = save(&cpu);
+
+        let mut bad_magic = state.clone();
+        bad_magic[0] = b'X';
+        assert_eq!(load(&mut cpu, &bad_magic), Err(StateError::InvalidFormat));
+
+        let mut old = state.clone();
+        old[MAGIC.len()] = STATE_VERSION - 1;
+        assert_eq!(
+            load(&mut cpu, &old),
+            Err(StateError::UnsupportedVersion(STATE_VERSION - 1))
+        );
+
+        let truncated = &state[..state.len() - 1];
+        assert_eq!(load(&mut cpu, truncated), Err(StateError::InvalidFormat));
+
+        // A failed load leaves the machine as it was
+        assert_eq!(save(&cpu), before);
+    }
+
+    #[test]
+    fn rejects_another_rom() {
+        let state = save(&running());
+        let mut cpu = CPU::new(Box::new(NullDisplay::new()), Options::default());
+        cpu.load_rom(&[0x12, 0x00]).unwrap();
+        assert_eq!(load(&mut cpu, &state), Err(StateError::RomMismatch));
+    }
+}
@@ -0,0 +1,97 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Keeps the SUPER-CHIP RPL user flags of a ROM between runs
+pub trait FlagStorage {
+    fn load(&mut self) -> Option<[u8; 16]>;
+    fn save(&mut self, flags: &[u8; 16]);
+}
+
+// Flag storage that forgets everything, the flags start out cleared
+pub struct NullStorage;
+
+impl FlagStorage for NullStorage {
+    fn load(&mut self) -> Option<[u8; 16]> {
+        None
+    }
+
+    fn save(&mut self, _flags: &[u8; 16]) {}
+}
+
+// Flag storage backed by a 16 byte file
+pub struct FileStorage {
+    path: PathBuf,
+}
+
+impl FileStorage {
+    pub fn new(path: &Path) -> FileStorage {
+        FileStorage {
+            path: path.to_path_buf(),
+        }
+    }
+
+    // The flags file of `rom` in the user data directory
+    pub fn for_rom(rom: &[u8]) -> Option<FileStorage> {
+        let path = data_dir()?
+            .join("rpl")
+            .join(format!("{:016x}.rpl", rom_hash(rom)));
+        Some(FileStorage { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn write(&self, flags: &[u8; 16]) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, flags)
+    }
+}
+
+impl FlagStorage for FileStorage {
+    fn load(&mut self) -> Option<[u8; 16]> {
+        // A missing or truncated file counts as cleared flags
+        let bytes = fs::read(&self.path).ok()?;
+        let mut flags = [0; 16];
+        let count = bytes.len().min(flags.len());
+        flags[..count].copy_from_slice(&bytes[..count]);
+        Some(flags)
+    }
+
+    fn save(&mut self, flags: &[u8; 16]) {
+        // Losing a high score is not worth stopping the game for
+        let _ = self.write(flags);
+    }
+}
+
+// The file of save state slot `slot` of a ROM in the user data directory
+pub fn state_path(rom_hash: u64, slot: u8) -> Option<PathBuf> {
+    let name = format!("{:016x}.{}.state", rom_hash, slot);
+    Some(data_dir()?.join("states").join(name))
+}
+
+// 64-bit FNV-1a hash of a ROM image, identifies a ROM across runs
+pub fn rom_hash(rom: &[u8]) -> u64 {
+    fnv1a(rom)
+}
+
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xCBF2_9CE4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01B3)
+    })
+}
+
+// Directory the interpreter keeps its files in, following the XDG base
+// directory spec on Unix and using %APPDATA% on Windows
+pub fn data_dir() -> Option<PathBuf> {
+    let base = env::var_os("XDG_DATA_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")))?;
+    Some(base.join("rust-chip8-emulator"))
+}
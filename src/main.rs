@@ -1,62 +1,75 @@
-mod lib;
+mod cli;
+#[cfg(feature = "sdl")]
+mod frontend;
+mod headless;
+mod regress;
+#[cfg(feature = "sdl")]
+mod window;
 
 use clap::Parser;
-use lib::cpu::{Options, CPU};
-use lib::graphics::Display;
-use std::{thread, time};
-
-#[derive(Parser, Debug)]
-#[clap(author, version, about, long_about = None)]
-struct Args {
-    #[clap(short)]
-    rom_file_path: String,
-    #[clap(short)]
-    put_value_of_vy_into_vx_before_shifting: bool,
-    #[clap(short)]
-    jump_to_nnn_plus_the_value_in_v0: bool,
-    #[clap(short)]
-    increment_i_when_storing_loading_memory: bool,
-}
+use cli::{Args, Command};
+use rust_chip8_emulator::disasm::disassemble;
+use rust_chip8_emulator::movie::Movie;
+use std::fs;
+use std::path::Path;
+use std::process;
 
 fn main() {
-    let (
-        rom_file_path,
-        put_value_of_vy_into_vx_before_shifting,
-        jump_to_nnn_plus_the_value_in_v0,
-        increment_i_when_storing_loading_memory,
-    );
-
     // Parse the command line arguments
-    Args {
-        rom_file_path,
-        put_value_of_vy_into_vx_before_shifting,
-        jump_to_nnn_plus_the_value_in_v0,
-        increment_i_when_storing_loading_memory,
-    } = Args::parse();
+    let args = Args::parse();
 
-    // Read the ROM file
-    let rom = std::fs::read(rom_file_path).expect("Failed to read ROM data");
-    if rom.len() >= 3585 {
-        panic!("ROM is too large! size: {}", rom.len());
+    // Subcommands do not play a ROM
+    match &args.command {
+        Some(Command::Regress { manifest, update }) => {
+            process::exit(regress::run(manifest, *update));
+        }
+        Some(Command::Disasm {
+            rom,
+            syntax,
+            platform,
+        }) => {
+            let bytes = fs::read(rom).unwrap_or_else(|error| {
+                eprintln!("{}: {}", rom.display(), error);
+                process::exit(1);
+            });
+            let instruction_set = platform.options().instruction_set;
+            print!("{}", disassemble(&bytes, instruction_set, *syntax));
+            process::exit(0);
+        }
+        None => {}
     }
 
-    // TODO: Initialize the display
-    let sdl_context = sdl2::init().unwrap();
-    let display = Display::new(sdl_context);
+    // Read the ROM file, which is required without a subcommand
+    let rom_file_path = args.rom_file_path.as_deref().unwrap_or_default();
+    let rom = std::fs::read(rom_file_path).expect("Failed to read ROM data");
 
-    // TODO: Initialize the CPU
-    let mut cpu = CPU::new(
-        display,
-        Options {
-            put_value_of_vy_into_vx_before_shifting,
-            jump_to_nnn_plus_the_value_in_v0,
-            increment_i_when_storing_loading_memory,
-        },
-    );
-    cpu.load_rom(rom);
+    // A replayed movie brings its own options, seed and keys
+    let replay = args.replay.as_deref().map(read_movie);
+    if args.headless {
+        process::exit(match &replay {
+            Some(movie) => headless::replay(&args, &rom, movie),
+            None => headless::run(&args, &rom),
+        });
+    }
 
-    loop {
-        thread::sleep(time::Duration::from_millis(1));
-        cpu.step();
+    // Playing in a window needs SDL
+    #[cfg(feature = "sdl")]
+    window::run(&args, &rom, replay);
+    #[cfg(not(feature = "sdl"))]
+    {
+        eprintln!("Built without SDL, only --headless runs are available");
+        process::exit(1);
     }
 }
+
+// Read a movie to replay, exiting if it is unusable
+fn read_movie(path: &Path) -> Movie {
+    let movie = match fs::read(path) {
+        Ok(bytes) => Movie::from_bytes(&bytes).map_err(|error| error.to_string()),
+        Err(error) => Err(error.to_string()),
+    };
+    movie.unwrap_or_else(|error| {
+        eprintln!("Failed to read movie {}: {}", path.display(), error);
+        process::exit(1);
+    })
+}
@@ -0,0 +1,256 @@
+use rust_chip8_emulator::image::Bitmap;
+use rust_chip8_emulator::{Chip8, Platform};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/*
+|  Manifest format, one ROM per line, # starts a comment
+|
+|  rom=roms/ibm.ch8 golden=golden/ibm.pbm frames=120 platform=schip
+|  rom=roms/keys.ch8 golden=golden/keys.pbm input=input/keys.txt seed=7
+|
+|  rom and golden are required, paths are relative to the manifest. frames
+|  defaults to 600, platform to schip, seed to 0 and cycles to the default
+|  cycles per frame. Golden images are plain PBM files.
+|
+|  Input script format, one key event per line, # starts a comment
+|
+|  60 press 5
+|  90 release 5
+|
+|  The event happens right before the frame with that number runs.
+*/
+struct Entry {
+    rom: PathBuf,
+    golden: PathBuf,
+    frames: u64,
+    platform: Platform,
+    input: Option<PathBuf>,
+    seed: u64,
+    cycles: Option<u32>,
+}
+
+struct KeyEvent {
+    frame: u64,
+    key: u8,
+    pressed: bool,
+}
+
+enum Outcome {
+    Pass,
+    Updated,
+    Mismatch(usize, PathBuf), // Pixels that differ and the diff image
+    Resized(String, PathBuf), // Sizes of the screen and the golden image, and the diff image
+    Error(String),
+}
+
+// Runs every ROM of the manifest and prints a summary table, returns the
+// exit code of the process
+pub fn run(manifest: &Path, update: bool) -> i32 {
+    let entries = match read_manifest(manifest) {
+        Ok(entries) => entries,
+        Err(error) => {
+            eprintln!("{}: {}", manifest.display(), error);
+            return 1;
+        }
+    };
+
+    let results: Vec<(&Entry, Outcome)> = entries
+        .iter()
+        .map(|entry| (entry, check(entry, update)))
+        .collect();
+
+    // Summary table, one row per ROM
+    let width = results
+        .iter()
+        .map(|(entry, _)| entry.rom.display().to_string().len())
+        .max()
+        .unwrap_or(0)
+        .max(3);
+    println!("{:width$}  {:>6}  RESULT", "ROM", "FRAMES", width = width);
+    let mut failed = 0;
+    for (entry, outcome) in &results {
+        let result = match outcome {
+            Outcome::Pass => "pass".to_string(),
+            Outcome::Updated => "updated".to_string(),
+            Outcome::Mismatch(pixels, diff) => {
+                format!("FAIL {} pixels differ, see {}", pixels, diff.display())
+            }
+            Outcome::Resized(sizes, diff) => format!("FAIL {}, see {}", sizes, diff.display()),
+            Outcome::Error(error) => format!("ERROR {}", error),
+        };
+        if matches!(
+            outcome,
+            Outcome::Mismatch(..) | Outcome::Resized(..) | Outcome::Error(_)
+        ) {
+            failed += 1;
+        }
+        println!(
+            "{:width$}  {:>6}  {}",
+            entry.rom.display(),
+            entry.frames,
+            result,
+            width = width
+        );
+    }
+    println!("{} passed, {} failed", results.len() - failed, failed);
+
+    if failed > 0 {
+        1
+    } else {
+        0
+    }
+}
+
+// Runs one ROM and compares its final screen with the golden image
+fn check(entry: &Entry, update: bool) -> Outcome {
+    let actual = match final_screen(entry) {
+        Ok(actual) => actual,
+        Err(error) => return Outcome::Error(error),
+    };
+
+    if update {
+        return match fs::write(&entry.golden, actual.to_pbm()) {
+            Ok(()) => Outcome::Updated,
+            Err(error) => Outcome::Error(format!("{}: {}", entry.golden.display(), error)),
+        };
+    }
+
+    let expected = match fs::read(&entry.golden)
+        .map_err(|error| error.to_string())
+        .and_then(|bytes| Bitmap::from_pbm(&bytes))
+    {
+        Ok(expected) => expected,
+        Err(error) => return Outcome::Error(format!("{}: {}", entry.golden.display(), error)),
+    };
+    let differences = expected.count_differences(&actual);
+    if differences == Some(0) {
+        return Outcome::Pass;
+    }
+
+    let diff = entry.golden.with_extension("diff.ppm");
+    match fs::write(&diff, expected.diff_ppm(&actual)) {
+        Ok(()) => match differences {
+            Some(differences) => Outcome::Mismatch(differences, diff),
+            None => Outcome::Resized(
+                format!(
+                    "screen is {}x{}, golden image is {}x{}",
+                    actual.width, actual.height, expected.width, expected.height
+                ),
+                diff,
+            ),
+        },
+        Err(error) => Outcome::Error(format!("{}: {}", diff.display(), error)),
+    }
+}
+
+// Runs the ROM headless for the frames of the entry, pressing the keys of
+// its input script
+fn final_screen(entry: &Entry) -> Result<Bitmap, String> {
+    let rom = fs::read(&entry.rom).map_err(|error| error.to_string())?;
+    let events = match &entry.input {
+        Some(path) => read_input(path).map_err(|error| format!("{}: {}", path.display(), error))?,
+        None => Vec::new(),
+    };
+
+    let mut chip8 = Chip8::headless(entry.platform.options()).map_err(|error| error.to_string())?;
+    chip8.load_rom(&rom).map_err(|error| error.to_string())?;
+    chip8.set_seed(entry.seed);
+    if let Some(cycles) = entry.cycles {
+        chip8.set_cycles_per_frame(cycles);
+    }
+    for frame in 0..entry.frames {
+        for event in events.iter().filter(|event| event.frame == frame) {
+            chip8.set_key(event.key, event.pressed);
+        }
+        chip8
+            .run_frame()
+            .map_err(|error| format!("{} in frame {}", error, frame))?;
+    }
+    Ok(Bitmap::from_framebuffer(chip8.framebuffer()))
+}
+
+fn read_manifest(path: &Path) -> Result<Vec<Entry>, String> {
+    let text = fs::read_to_string(path).map_err(|error| error.to_string())?;
+    let base = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut entries = Vec::new();
+    for (number, line) in significant_lines(&text) {
+        let entry =
+            parse_entry(line, base).map_err(|error| format!("line {}: {}", number, error))?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+fn parse_entry(line: &str, base: &Path) -> Result<Entry, String> {
+    let mut rom = None;
+    let mut golden = None;
+    let mut entry = Entry {
+        rom: PathBuf::new(),
+        golden: PathBuf::new(),
+        frames: 600,
+        platform: Platform::default(),
+        input: None,
+        seed: 0,
+        cycles: None,
+    };
+    for field in line.split_whitespace() {
+        let (key, value) = field
+            .split_once('=')
+            .ok_or_else(|| format!("Expected key=value, got {}", field))?;
+        let invalid = |_| format!("Invalid {}: {}", key, value);
+        match key {
+            "rom" => rom = Some(base.join(value)),
+            "golden" => golden = Some(base.join(value)),
+            "frames" => entry.frames = value.parse().map_err(invalid)?,
+            "platform" => entry.platform = value.parse()?,
+            "input" => entry.input = Some(base.join(value)),
+            "seed" => entry.seed = value.parse().map_err(invalid)?,
+            "cycles" => entry.cycles = Some(value.parse().map_err(invalid)?),
+            _ => return Err(format!("Unknown key: {}", key)),
+        }
+    }
+    entry.rom = rom.ok_or("Missing rom")?;
+    entry.golden = golden.ok_or("Missing golden")?;
+    Ok(entry)
+}
+
+fn read_input(path: &Path) -> Result<Vec<KeyEvent>, String> {
+    let text = fs::read_to_string(path).map_err(|error| error.to_string())?;
+    let mut events = Vec::new();
+    for (number, line) in significant_lines(&text) {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let event = match fields[..] {
+            [frame, action, key] => KeyEvent {
+                frame: frame
+                    .parse()
+                    .map_err(|_| format!("line {}: Invalid frame", number))?,
+                key: u8::from_str_radix(key, 16)
+                    .ok()
+                    .filter(|&key| key <= 0xF)
+                    .ok_or_else(|| format!("line {}: Invalid key {}", number, key))?,
+                pressed: match action {
+                    "press" => true,
+                    "release" => false,
+                    _ => return Err(format!("line {}: Unknown action {}", number, action)),
+                },
+            },
+            _ => {
+                return Err(format!(
+                    "line {}: Expected <frame> press|release <key>",
+                    number
+                ))
+            }
+        };
+        events.push(event);
+    }
+    Ok(events)
+}
+
+// Numbered lines without comments and blank lines
+fn significant_lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.split('#').next().unwrap_or("").trim()))
+        .filter(|(_, line)| !line.is_empty())
+}
@@ -0,0 +1,237 @@
+use crate::cli::Args;
+use crate::frontend::audio::Speaker;
+use crate::frontend::input::{keymap, state_slot};
+use crate::frontend::scheduler::Scheduler;
+use crate::frontend::video::Display;
+use rust_chip8_emulator::movie::Movie;
+use rust_chip8_emulator::rewind::RewindBuffer;
+use rust_chip8_emulator::storage::{state_path, FileStorage};
+use rust_chip8_emulator::{Chip8, Chip8Error, FRAME_RATE};
+use sdl2::event::Event;
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::messagebox::{show_simple_message_box, MessageBoxFlag};
+use std::fs;
+use std::path::Path;
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Plays the ROM in a window until it exits or the window is closed, or
+// replays `replay` in it
+pub fn run(args: &Args, rom: &[u8], replay: Option<Movie>) {
+    // TODO: Initialize the display
+    let sdl_context = sdl2::init().unwrap();
+    let display = Display::new(&sdl_context);
+
+    // Initialize the machine
+    let options = replay
+        .as_ref()
+        .map_or(args.options(), |movie| movie.options);
+    let mut chip8 = Chip8::new(Box::new(display), options).unwrap_or_else(|error| fail(&error));
+
+    // The machine stays silent when muted or without an audio device
+    if !args.mute {
+        match Speaker::new(&sdl_context, args.tone()) {
+            Ok(speaker) => chip8.set_audio(Box::new(speaker)),
+            Err(error) => eprintln!("No sound, failed to open the audio device: {}", error),
+        }
+    }
+    if let Some(cycles) = args.cycles() {
+        chip8.set_cycles_per_frame(cycles);
+    }
+    if let Err(error) = chip8.load_rom(rom) {
+        fail(&error);
+    }
+
+    // Random numbers differ between runs unless a seed is given
+    let seed = args.seed.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_nanos() as u64)
+    });
+    chip8.set_seed(seed);
+    if let Some(movie) = &replay {
+        if let Err(error) = movie.start(&mut chip8) {
+            eprintln!("Failed to replay movie: {}", error);
+            process::exit(1);
+        }
+    }
+    let mut recording = args.record.as_ref().map(|_| Movie::new(&chip8, seed));
+
+    // Movies are about a single session, so rewinding, loading states and
+    // flags saved by earlier runs would break them
+    let movie_active = replay.is_some() || recording.is_some();
+    let mut replayed_frames = 0;
+    let mut desynced = false;
+
+    // Restore the RPL user flags the ROM saved in an earlier run
+    if !args.no_persist_flags && !movie_active {
+        if let Some(storage) = FileStorage::for_rom(rom) {
+            chip8.set_flag_storage(Box::new(storage));
+        }
+    }
+
+    // Keep the last frames around to rewind while Backspace is held
+    let mut rewind = RewindBuffer::new(
+        (args.rewind_seconds * FRAME_RATE) as usize,
+        args.rewind_megabytes * 1024 * 1024,
+    );
+    let mut rewinding = false;
+    rewind.record(&chip8);
+
+    let mut event_pump = sdl_context.event_pump().unwrap();
+    let mut scheduler = Scheduler::new();
+    'running: loop {
+        // Handle window and keyboard events
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => break 'running,
+                Event::KeyDown {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => rewinding = !movie_active,
+                Event::KeyUp {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => rewinding = false,
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    keymod,
+                    ..
+                } => {
+                    // Shift + F1 through F10 saves a slot, F1 through F10 loads it
+                    if let Some(slot) = state_slot(keycode) {
+                        if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                            save_slot(&chip8, slot);
+                        } else if movie_active {
+                            eprintln!("Save states cannot be loaded during a movie");
+                        } else {
+                            load_slot(&mut chip8, slot);
+                        }
+                    }
+                    if let Some(key) = keymap(keycode) {
+                        chip8.press_key(key);
+                    }
+                }
+                Event::KeyUp {
+                    keycode: Some(keycode),
+                    ..
+                } => {
+                    if let Some(key) = keymap(keycode) {
+                        chip8.release_key(key);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // Run the frames that are due, each one ticks the timers and presents
+        for _ in 0..scheduler.wait() {
+            if rewinding {
+                // Step back a frame, the keys held now stay held
+                let keys = *chip8.keypad();
+                rewind.rewind(&mut chip8);
+                for (key, &pressed) in keys.iter().enumerate() {
+                    chip8.set_key(key as u8, pressed);
+                }
+                continue;
+            }
+            if let Some(movie) = &replay {
+                // The movie ends the session once all its frames are replayed
+                if replayed_frames == movie.frames.len() {
+                    println!("Replayed {} frames", replayed_frames);
+                    break 'running;
+                }
+                match movie.replay_frame(replayed_frames, &mut chip8) {
+                    Ok(in_sync) if !in_sync && !desynced => {
+                        eprintln!("Desync at frame {}", replayed_frames);
+                        desynced = true;
+                    }
+                    Ok(_) => {}
+                    Err(error) => fail(&error),
+                }
+                replayed_frames += 1;
+                continue;
+            }
+            if let Err(error) = chip8.run_frame() {
+                save_movie(args.record.as_deref(), &recording);
+                fail(&error);
+            }
+            if let Some(movie) = &mut recording {
+                movie.record_frame(&chip8);
+            }
+            rewind.record(&chip8);
+        }
+
+        // The ROM exited with 00FD
+        if chip8.is_halted() {
+            break 'running;
+        }
+    }
+
+    save_movie(args.record.as_deref(), &recording);
+}
+// Write the recorded movie, if recording
+fn save_movie(path: Option<&Path>, movie: &Option<Movie>) {
+    if let (Some(path), Some(movie)) = (path, movie) {
+        match fs::write(path, movie.to_bytes()) {
+            Ok(()) => eprintln!(
+                "Recorded {} frames to {}",
+                movie.frames.len(),
+                path.display()
+            ),
+            Err(error) => eprintln!("Failed to write movie {}: {}", path.display(), error),
+        }
+    }
+}
+
+// Save the machine to a save state slot of the ROM
+fn save_slot(chip8: &Chip8, slot: u8) {
+    let path = match state_path(chip8.rom_hash(), slot) {
+        Some(path) => path,
+        None => return eprintln!("No data directory to keep save states in"),
+    };
+    let result = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&path, chip8.save_state()));
+    match result {
+        Ok(()) => eprintln!("Saved state {}", slot),
+        Err(error) => eprintln!("Failed to save state {}: {}", slot, error),
+    }
+}
+
+// Restore the machine from a save state slot of the ROM
+fn load_slot(chip8: &mut Chip8, slot: u8) {
+    let state = match state_path(chip8.rom_hash(), slot).map(fs::read) {
+        Some(Ok(state)) => state,
+        _ => return eprintln!("No save state in slot {}", slot),
+    };
+    // The saved keypad is not what the player holds now, keys pressed when
+    // saving would stay down until pressed again
+    let keys = *chip8.keypad();
+    match chip8.load_state(&state) {
+        Ok(()) => {
+            for (key, &pressed) in keys.iter().enumerate() {
+                chip8.set_key(key as u8, pressed);
+            }
+            eprintln!("Loaded state {}", slot);
+        }
+        Err(error) => eprintln!("Failed to load state {}: {}", slot, error),
+    }
+}
+
+// Report an error that stopped the machine and exit
+fn fail(error: &Chip8Error) -> ! {
+    eprintln!("{}", error);
+    let _ = show_simple_message_box(
+        MessageBoxFlag::ERROR,
+        "CHIP-8 interpreter",
+        &error.to_string(),
+        None,
+    );
+    process::exit(1);
+}